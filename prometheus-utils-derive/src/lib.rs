@@ -0,0 +1,90 @@
+//! Derive macros for the [`prometheus-utils`][prometheus-utils] crate.
+//!
+//! This crate is not intended to be used directly. Enable the `derive` feature of
+//! `prometheus-utils` instead, which re-exports the macros defined here.
+//!
+//! [prometheus-utils]: https://docs.rs/prometheus-utils
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Data, DeriveInput, Fields};
+
+/// Derive the `Labels` trait for a struct with named fields.
+///
+/// Each field becomes one label: the field's name is used as the label name, and the field's
+/// value, converted through the `LabelValue` trait, is used as the label value. Fields appear in
+/// the order they are declared.
+///
+/// `possible_label_values` is the cartesian product of the possible values of each field. Types
+/// declared with `label_enum!` report all of their variants; types without a finite set of values
+/// (like `String`) report none, in which case the metric is not pre-populated.
+#[proc_macro_derive(Labels)]
+pub fn derive_labels(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_labels(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_labels(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.span(),
+                    "`Labels` can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "`Labels` can only be derived for structs with named fields",
+            ))
+        }
+    };
+
+    let idents: Vec<_> = fields.iter().filter_map(|f| f.ident.as_ref()).collect();
+    let types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+    // Raw identifiers like `r#type` should produce the label name `type`.
+    let names: Vec<_> = idents
+        .iter()
+        .map(|ident| ident.to_string().trim_start_matches("r#").to_owned())
+        .collect();
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::prometheus_utils::Labels for #name #ty_generics #where_clause {
+            fn label_names() -> ::std::vec::Vec<&'static str> {
+                ::std::vec![#(#names),*]
+            }
+
+            fn possible_label_values() -> ::std::vec::Vec<::prometheus_utils::LabelValues<'static>> {
+                ::prometheus_utils::__cartesian_product(::std::vec![
+                    #(<#types as ::prometheus_utils::LabelValue>::possible_values()),*
+                ])
+            }
+
+            fn label_values(&self) -> ::prometheus_utils::LabelValues<'_> {
+                ::std::vec![
+                    #(::prometheus_utils::LabelValue::as_label_value(&self.#idents)),*
+                ]
+            }
+
+            fn visit_label_values<'__a, __R>(
+                &'__a self,
+                f: impl ::std::ops::FnOnce(&[&'__a str]) -> __R,
+            ) -> __R {
+                f(&[
+                    #(::prometheus_utils::LabelValue::as_label_value(&self.#idents)),*
+                ])
+            }
+        }
+    })
+}
@@ -0,0 +1,82 @@
+use prometheus_utils::{label_enum, IntCounterWithLabels, Labels};
+
+label_enum! {
+    enum Method {
+        Get,
+        Post,
+    }
+}
+
+label_enum! {
+    enum Outcome {
+        Ok,
+        ServerError,
+    }
+}
+
+#[derive(Labels)]
+struct RequestLabels {
+    method: Method,
+    outcome: Outcome,
+}
+
+#[derive(Labels)]
+struct TenantLabels<'a> {
+    tenant: &'a str,
+    r#type: Method,
+}
+
+#[test]
+fn derived_labels_use_field_names_and_values() {
+    assert_eq!(RequestLabels::label_names(), vec!["method", "outcome"]);
+
+    let labels = RequestLabels {
+        method: Method::Post,
+        outcome: Outcome::ServerError,
+    };
+    assert_eq!(labels.label_values(), vec!["post", "server_error"]);
+}
+
+#[test]
+fn derived_possible_values_are_the_cartesian_product() {
+    assert_eq!(
+        RequestLabels::possible_label_values(),
+        vec![
+            vec!["get", "ok"],
+            vec!["get", "server_error"],
+            vec!["post", "ok"],
+            vec!["post", "server_error"],
+        ]
+    );
+}
+
+#[test]
+fn derived_labels_with_unbounded_fields_have_no_possible_values() {
+    assert_eq!(TenantLabels::label_names(), vec!["tenant", "type"]);
+    assert!(TenantLabels::possible_label_values().is_empty());
+
+    let labels = TenantLabels {
+        tenant: "fastly",
+        r#type: Method::Get,
+    };
+    assert_eq!(labels.label_values(), vec!["fastly", "get"]);
+}
+
+#[test]
+fn derived_labels_prepopulate_counters() {
+    let counter = IntCounterWithLabels::<RequestLabels>::register_new(
+        "derived_requests",
+        "requests, labeled by a derived label struct",
+    );
+    counter.inc(&RequestLabels {
+        method: Method::Get,
+        outcome: Outcome::Ok,
+    });
+
+    let families = prometheus::gather();
+    let family = families
+        .iter()
+        .find(|family| family.get_name() == "derived_requests")
+        .expect("counter is registered");
+    assert_eq!(family.get_metric().len(), 4);
+}
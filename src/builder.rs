@@ -0,0 +1,223 @@
+//! A builder for configuring labeled metrics before they are registered.
+
+use crate::{
+    error::Result, GenericCounterWithLabels, GenericGaugeWithLabels, HistogramWithLabels, Labels,
+};
+use prometheus::{core::Atomic, HistogramOpts, IntCounter, Opts, Registry};
+use std::{collections::HashMap, marker::PhantomData, time::Duration};
+
+/// A builder for labeled metrics like [`IntCounterWithLabels`][crate::IntCounterWithLabels].
+///
+/// Created by calling `builder` on a labeled metric type, such as
+/// [`GenericCounterWithLabels::builder`]. A builder can set the namespace, subsystem and constant
+/// labels of a metric, all of which are part of the metric's identity, and so must be decided
+/// before the metric is registered.
+///
+/// # Examples
+///
+/// ```
+/// use prometheus_utils::{IntCounterWithLabels, TimingBucket};
+///
+/// let registry = prometheus::Registry::new();
+/// let counter = IntCounterWithLabels::<TimingBucket>::builder("requests_total", "requests")
+///     .namespace("my_service")
+///     .const_label("region", "us-east")
+///     .register_in(&registry)
+///     .unwrap();
+/// ```
+#[must_use = "builders do nothing unless a metric is built or registered"]
+pub struct LabeledMetricBuilder<M> {
+    opts: Opts,
+    buckets: Option<Vec<f64>>,
+    cardinality_limit: Option<(usize, IntCounter)>,
+    idle_ttl: Option<Duration>,
+    _metric: PhantomData<M>,
+}
+
+impl<M: sealed::FromOpts> LabeledMetricBuilder<M> {
+    /// Create a builder for a metric with the given `name` and `help` text.
+    pub fn new(name: &str, help: &str) -> Self {
+        Self {
+            opts: Opts::new(name, help),
+            buckets: None,
+            cardinality_limit: None,
+            idle_ttl: None,
+            _metric: PhantomData,
+        }
+    }
+
+    /// Set the namespace of the metric, which is prepended to its name.
+    pub fn namespace<S: Into<String>>(mut self, namespace: S) -> Self {
+        self.opts = self.opts.namespace(namespace);
+        self
+    }
+
+    /// Set the subsystem of the metric, which is prepended to its name after the namespace.
+    pub fn subsystem<S: Into<String>>(mut self, subsystem: S) -> Self {
+        self.opts = self.opts.subsystem(subsystem);
+        self
+    }
+
+    /// Add a constant label, whose value is the same for every event of the metric.
+    pub fn const_label<S1: Into<String>, S2: Into<String>>(mut self, name: S1, value: S2) -> Self {
+        self.opts = self.opts.const_label(name, value);
+        self
+    }
+
+    /// Set the constant labels of the metric, replacing any added so far.
+    pub fn const_labels(mut self, const_labels: HashMap<String, String>) -> Self {
+        self.opts = self.opts.const_labels(const_labels);
+        self
+    }
+
+    /// Limit the number of distinct label sets the metric may contain.
+    ///
+    /// See [`GenericCounterWithLabels::with_cardinality_limit`].
+    pub fn cardinality_limit(mut self, max_series: usize, overflow: IntCounter) -> Self {
+        self.cardinality_limit = Some((max_series, overflow));
+        self
+    }
+
+    /// Remove label sets that have not been updated within `ttl`.
+    ///
+    /// See [`GenericCounterWithLabels::with_idle_ttl`].
+    pub fn idle_ttl(mut self, ttl: Duration) -> Self {
+        self.idle_ttl = Some(ttl);
+        self
+    }
+
+    /// Construct the metric, without registering it.
+    pub fn build(self) -> Result<M> {
+        let mut metric = M::from_opts(self.opts, self.buckets)?;
+        if let Some((max_series, overflow)) = self.cardinality_limit {
+            metric = metric.with_cardinality_limit(max_series, overflow);
+        }
+        if let Some(ttl) = self.idle_ttl {
+            metric = metric.with_idle_ttl(ttl);
+        }
+        Ok(metric)
+    }
+
+    /// Construct the metric and register it in `registry`.
+    pub fn register_in(self, registry: &Registry) -> Result<M> {
+        let metric = self.build()?;
+        metric.register_in(registry)?;
+        Ok(metric)
+    }
+
+    /// Construct the metric and register it in the default registry.
+    pub fn register(self) -> Result<M> {
+        self.register_in(prometheus::default_registry())
+    }
+}
+
+impl<L: Labels> LabeledMetricBuilder<HistogramWithLabels<L>> {
+    /// Set the buckets of the histogram.
+    pub fn buckets(mut self, buckets: Vec<f64>) -> Self {
+        self.buckets = Some(buckets);
+        self
+    }
+}
+
+mod sealed {
+    use super::*;
+
+    /// Labeled metric types that can be constructed by a [`LabeledMetricBuilder`].
+    pub trait FromOpts: Sized {
+        fn from_opts(opts: Opts, buckets: Option<Vec<f64>>) -> Result<Self>;
+        fn with_cardinality_limit(self, max_series: usize, overflow: IntCounter) -> Self;
+        fn with_idle_ttl(self, ttl: Duration) -> Self;
+        fn register_in(&self, registry: &Registry) -> Result<()>;
+    }
+
+    impl<P: Atomic + 'static, L: Labels> FromOpts for GenericCounterWithLabels<P, L> {
+        fn from_opts(opts: Opts, _: Option<Vec<f64>>) -> Result<Self> {
+            Self::from_opts(opts)
+        }
+        fn with_cardinality_limit(self, max_series: usize, overflow: IntCounter) -> Self {
+            self.with_cardinality_limit(max_series, overflow)
+        }
+        fn with_idle_ttl(self, ttl: Duration) -> Self {
+            self.with_idle_ttl(ttl)
+        }
+        fn register_in(&self, registry: &Registry) -> Result<()> {
+            self.register_in(registry)
+        }
+    }
+
+    impl<P: Atomic + 'static, L: Labels> FromOpts for GenericGaugeWithLabels<P, L> {
+        fn from_opts(opts: Opts, _: Option<Vec<f64>>) -> Result<Self> {
+            Self::from_opts(opts)
+        }
+        fn with_cardinality_limit(self, max_series: usize, overflow: IntCounter) -> Self {
+            self.with_cardinality_limit(max_series, overflow)
+        }
+        fn with_idle_ttl(self, ttl: Duration) -> Self {
+            self.with_idle_ttl(ttl)
+        }
+        fn register_in(&self, registry: &Registry) -> Result<()> {
+            self.register_in(registry)
+        }
+    }
+
+    impl<L: Labels> FromOpts for HistogramWithLabels<L> {
+        fn from_opts(opts: Opts, buckets: Option<Vec<f64>>) -> Result<Self> {
+            let mut opts = HistogramOpts::from(opts);
+            if let Some(buckets) = buckets {
+                opts = opts.buckets(buckets);
+            }
+            Self::from_opts(opts)
+        }
+        fn with_cardinality_limit(self, max_series: usize, overflow: IntCounter) -> Self {
+            self.with_cardinality_limit(max_series, overflow)
+        }
+        fn with_idle_ttl(self, ttl: Duration) -> Self {
+            self.with_idle_ttl(ttl)
+        }
+        fn register_in(&self, registry: &Registry) -> Result<()> {
+            self.register_in(registry)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{HistogramWithLabels, IntGaugeWithLabels, TimingBucket};
+    use prometheus::Registry;
+
+    #[test]
+    fn builder_sets_name_prefixes_and_const_labels() {
+        let registry = Registry::new();
+        let gauge = IntGaugeWithLabels::<TimingBucket>::builder("latency", "help")
+            .namespace("service")
+            .subsystem("http")
+            .const_label("region", "us-east")
+            .register_in(&registry)
+            .unwrap();
+        gauge.set(&TimingBucket::P50, 3);
+
+        let families = registry.gather();
+        assert_eq!(families[0].get_name(), "service_http_latency");
+        let labels = families[0].get_metric()[0].get_label();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels[0].get_name(), "bucket");
+        assert_eq!(labels[1].get_name(), "region");
+        assert_eq!(labels[1].get_value(), "us-east");
+    }
+
+    #[test]
+    fn builder_sets_histogram_buckets() {
+        let registry = Registry::new();
+        let histogram = HistogramWithLabels::<TimingBucket>::builder("sizes", "help")
+            .buckets(vec![1.0, 10.0])
+            .register_in(&registry)
+            .unwrap();
+        histogram.observe(&TimingBucket::Max, 5.0);
+
+        let families = registry.gather();
+        let buckets = families[0].get_metric()[0].get_histogram().get_bucket();
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].get_cumulative_count(), 0);
+        assert_eq!(buckets[1].get_cumulative_count(), 1);
+    }
+}
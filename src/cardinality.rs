@@ -0,0 +1,114 @@
+//! Limits on the number of distinct label sets a labeled metric may create.
+
+use parking_lot::RwLock;
+use prometheus::IntCounter;
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+};
+
+/// The label value used in place of every label once a metric's cardinality limit is reached.
+pub const OVERFLOW_LABEL_VALUE: &str = "__other__";
+
+/// Tracks the label sets used by a labeled metric, redirecting new label sets to an overflow
+/// series once `max_series` distinct label sets have been seen.
+pub(crate) struct CardinalityLimiter {
+    max_series: usize,
+    /// Hashes of the label sets admitted so far. Like the `prometheus` crate's own metric vectors,
+    /// label sets are identified by their hash alone.
+    series: RwLock<HashSet<u64>>,
+    overflow_values: Vec<&'static str>,
+    overflow: IntCounter,
+}
+
+impl CardinalityLimiter {
+    pub(crate) fn new(max_series: usize, label_count: usize, overflow: IntCounter) -> Self {
+        Self {
+            max_series,
+            series: RwLock::new(HashSet::new()),
+            overflow_values: vec![OVERFLOW_LABEL_VALUE; label_count],
+            overflow,
+        }
+    }
+
+    /// Return the label values to use for an event with the given `vals`.
+    ///
+    /// If `vals` has not been seen before and the limit has been reached, the overflow label
+    /// values are returned instead, and the overflow counter is incremented.
+    pub(crate) fn admit<'a>(&'a self, vals: &'a [&'a str]) -> &'a [&'a str] {
+        if self.try_admit(vals) {
+            vals
+        } else {
+            self.overflow.inc();
+            &self.overflow_values
+        }
+    }
+
+    /// Return the label values to bind a handle for `vals` to, along with the overflow counter
+    /// if the handle was redirected to the overflow series.
+    ///
+    /// Nothing is counted here. Instead, updates made through a redirected handle should each
+    /// increment the returned counter.
+    pub(crate) fn admit_handle<'a>(
+        &'a self,
+        vals: &'a [&'a str],
+    ) -> (&'a [&'a str], Option<IntCounter>) {
+        if self.try_admit(vals) {
+            (vals, None)
+        } else {
+            (&self.overflow_values, Some(self.overflow.clone()))
+        }
+    }
+
+    /// Admit `vals` if it has been seen before or there is room under the limit, returning
+    /// whether it was admitted. Unlike [`CardinalityLimiter::admit`], nothing is counted when
+    /// `vals` does not fit.
+    pub(crate) fn try_admit(&self, vals: &[&str]) -> bool {
+        if vals == self.overflow_values.as_slice() {
+            return true;
+        }
+
+        let hash = hash_label_values(vals);
+        if self.series.read().contains(&hash) {
+            return true;
+        }
+
+        let mut series = self.series.write();
+        if series.contains(&hash) || series.len() < self.max_series {
+            series.insert(hash);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Return the label values under which `vals` has been recorded, without admitting them.
+    ///
+    /// Label sets that have not been admitted are read from the overflow series, and are not
+    /// counted as redirected events.
+    pub(crate) fn resolve<'a>(&'a self, vals: &'a [&'a str]) -> &'a [&'a str] {
+        if vals == self.overflow_values.as_slice()
+            || self.series.read().contains(&hash_label_values(vals))
+        {
+            vals
+        } else {
+            &self.overflow_values
+        }
+    }
+
+    /// Forget the label set `vals`, freeing its place towards the limit.
+    pub(crate) fn forget(&self, vals: &[&str]) {
+        self.series.write().remove(&hash_label_values(vals));
+    }
+
+    /// Forget every label set admitted so far.
+    pub(crate) fn clear(&self) {
+        self.series.write().clear();
+    }
+}
+
+pub(crate) fn hash_label_values(vals: &[&str]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    vals.hash(&mut hasher);
+    hasher.finish()
+}
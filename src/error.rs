@@ -0,0 +1,70 @@
+//! Errors produced when constructing or registering metrics, or parsing label values.
+
+use std::fmt;
+
+/// An error produced when constructing or registering a metric.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The underlying `prometheus` crate rejected the metric.
+    ///
+    /// This covers invalid metric or label names, as well as registering a metric whose name
+    /// collides with one already present in a [`Registry`][prometheus::Registry].
+    Prometheus(prometheus::Error),
+}
+
+/// A specialized [`Result`][std::result::Result] type for fallible metric operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Prometheus(err) => write!(f, "prometheus error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Prometheus(err) => Some(err),
+        }
+    }
+}
+
+impl From<prometheus::Error> for Error {
+    fn from(err: prometheus::Error) -> Self {
+        Error::Prometheus(err)
+    }
+}
+
+/// An error returned when parsing a string that is not the label value of any variant of a
+/// [`label_enum!`][crate::label_enum] type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseLabelError {
+    type_name: &'static str,
+    value: String,
+}
+
+impl ParseLabelError {
+    // This is only public for use by `label_enum!`.
+    #[doc(hidden)]
+    pub fn new(type_name: &'static str, value: &str) -> Self {
+        Self {
+            type_name,
+            value: value.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for ParseLabelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` is not a label value of `{}`",
+            self.value, self.type_name
+        )
+    }
+}
+
+impl std::error::Error for ParseLabelError {}
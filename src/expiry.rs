@@ -0,0 +1,141 @@
+//! Expiry of labeled metric series that have not been updated recently.
+
+use crate::cardinality::hash_label_values;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Tracks when each label set of a labeled metric was last updated, so that idle series can be
+/// removed.
+///
+/// Every labeled metric has one, shared with the collector it registers, so that a TTL set after
+/// registration still applies at gather time. Updates are not tracked until a TTL is set, but
+/// label sets pinned by guards are, so that they do not expire while the guards are held.
+pub(crate) struct IdleExpiry {
+    /// The TTL in nanoseconds, or `0` if label sets do not expire.
+    ttl_nanos: AtomicU64,
+    /// The label values, last update time and number of pins of each label set, keyed by the
+    /// hash of the label values.
+    last_touched: Mutex<HashMap<u64, Entry>>,
+}
+
+struct Entry {
+    vals: Vec<String>,
+    touched: Instant,
+    /// The number of live guards holding the label set. Pinned label sets never expire.
+    pins: usize,
+}
+
+impl Entry {
+    fn new(vals: &[&str], touched: Instant) -> Self {
+        Self {
+            vals: vals.iter().map(|v| v.to_string()).collect(),
+            touched,
+            pins: 0,
+        }
+    }
+}
+
+impl IdleExpiry {
+    pub(crate) fn new() -> Self {
+        Self {
+            ttl_nanos: AtomicU64::new(0),
+            last_touched: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Expire label sets that have not been updated within `ttl`.
+    pub(crate) fn set_ttl(&self, ttl: Duration) {
+        let nanos = u64::try_from(ttl.as_nanos()).unwrap_or(u64::MAX).max(1);
+        self.ttl_nanos.store(nanos, Ordering::SeqCst);
+    }
+
+    fn ttl(&self) -> Option<Duration> {
+        match self.ttl_nanos.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    /// Record that the label set `vals` was just updated.
+    pub(crate) fn touch(&self, vals: &[&str]) {
+        if self.ttl().is_none() {
+            return;
+        }
+        let now = Instant::now();
+        self.last_touched
+            .lock()
+            .entry(hash_label_values(vals))
+            .and_modify(|entry| entry.touched = now)
+            .or_insert_with(|| Entry::new(vals, now));
+    }
+
+    /// Record that the label set `vals` was just updated, and keep it from expiring until
+    /// [`IdleExpiry::unpin`] is called with the returned key.
+    ///
+    /// Returns `None` without tracking anything if no TTL is set. A TTL can only be set on a
+    /// metric that is not borrowed by any guard, so such pins would never matter.
+    pub(crate) fn pin(&self, vals: &[&str]) -> Option<u64> {
+        self.ttl()?;
+        let key = hash_label_values(vals);
+        let now = Instant::now();
+        let mut last_touched = self.last_touched.lock();
+        let entry = last_touched
+            .entry(key)
+            .or_insert_with(|| Entry::new(vals, now));
+        entry.touched = now;
+        entry.pins += 1;
+        Some(key)
+    }
+
+    /// Release a pin taken by [`IdleExpiry::pin`], recording that its label set was just updated.
+    pub(crate) fn unpin(&self, key: Option<u64>) {
+        let key = match key {
+            Some(key) => key,
+            None => return,
+        };
+        if let Some(entry) = self.last_touched.lock().get_mut(&key) {
+            entry.touched = Instant::now();
+            entry.pins = entry.pins.saturating_sub(1);
+        }
+    }
+
+    /// Stop tracking the label set `vals`.
+    pub(crate) fn forget(&self, vals: &[&str]) {
+        self.last_touched.lock().remove(&hash_label_values(vals));
+    }
+
+    /// Stop tracking every label set.
+    pub(crate) fn clear(&self) {
+        self.last_touched.lock().clear();
+    }
+
+    /// Stop tracking every unpinned label set that has not been updated within the TTL, calling
+    /// `remove` with each of them, and return how many there were.
+    ///
+    /// `remove` is called while the label sets are locked, so a label set cannot be updated
+    /// between being found idle and being removed.
+    pub(crate) fn remove_expired(&self, mut remove: impl FnMut(&[&str])) -> usize {
+        let ttl = match self.ttl() {
+            Some(ttl) => ttl,
+            None => return 0,
+        };
+        let now = Instant::now();
+        let mut removed = 0;
+        self.last_touched.lock().retain(|_, entry| {
+            if entry.pins > 0 || now.duration_since(entry.touched) < ttl {
+                true
+            } else {
+                let vals: Vec<&str> = entry.vals.iter().map(String::as_str).collect();
+                remove(&vals);
+                removed += 1;
+                false
+            }
+        });
+        removed
+    }
+}
@@ -1,72 +1,507 @@
-use crate::{IntCounterWithLabels, Labels};
-use prometheus::core::{Atomic, AtomicF64, AtomicI64, GenericCounter, GenericGauge, Number};
+#[cfg(feature = "leak-detection")]
+use crate::leaks::TrackedGuard;
+use crate::{GenericCounterWithLabels, GenericGaugeWithLabels, HistogramWithLabels, Labels};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use prometheus::core::{
+    Atomic, AtomicF64, AtomicI64, AtomicU64, Collector, Desc, GenericCounter, GenericGauge, Number,
+};
+use std::{
+    any::Any,
+    collections::HashMap,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{self, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
 /// An RAII-style guard for an [`AtomicI64`] gauge.
 ///
 /// Created by the methods on the [`GuardedGauge`] extension trait.
-pub type IntGaugeGuard = GenericGaugeGuard<AtomicI64>;
+pub type IntGaugeGuard<G = &'static GenericGauge<AtomicI64>> = GenericGaugeGuard<AtomicI64, G>;
 
 /// An RAII-style guard for an [`AtomicF64`] gauge.
 ///
 /// Created by the methods on the [`GuardedGauge`] extension trait.
-pub type GaugeGuard = GenericGaugeGuard<AtomicF64>;
+pub type GaugeGuard<G = &'static GenericGauge<AtomicF64>> = GenericGaugeGuard<AtomicF64, G>;
 
 /// An RAII-style guard for situations where we want to increment a gauge and then ensure that there
 /// is always a corresponding decrement.
 ///
+/// The guard holds on to the gauge through `G`, which is a reference to the gauge for guards
+/// created with [`GuardedGauge::guarded_inc`] and [`GuardedGauge::guarded_add`], and an
+/// [`Arc`] for guards created with [`GuardedGauge::guarded_inc_owned`] and
+/// [`GuardedGauge::guarded_add_owned`].
+///
 /// Created by the methods on the [`GuardedGauge`] extension trait.
-pub struct GenericGaugeGuard<P: Atomic + 'static> {
+pub struct GenericGaugeGuard<
+    P: Atomic + 'static,
+    G: Deref<Target = GenericGauge<P>> = &'static GenericGauge<P>,
+> {
     value: P::T,
-    gauge: &'static GenericGauge<P>,
+    gauge: G,
+    #[cfg(feature = "leak-detection")]
+    _tracked: TrackedGuard,
 }
 
 /// When a gauge guard is dropped, it will perform the corresponding decrement.
-impl<P: Atomic + 'static> Drop for GenericGaugeGuard<P> {
+impl<P: Atomic + 'static, G: Deref<Target = GenericGauge<P>>> Drop for GenericGaugeGuard<P, G> {
     fn drop(&mut self) {
         self.gauge.sub(self.value);
     }
 }
 
+impl<P: Atomic + 'static, G: Deref<Target = GenericGauge<P>>> GenericGaugeGuard<P, G> {
+    /// Increase the gauge, returning a guard that will perform the corresponding decrement.
+    fn new(gauge: G, value: P::T) -> Self {
+        gauge.add(value);
+        Self {
+            value,
+            #[cfg(feature = "leak-detection")]
+            _tracked: track_gauge(&gauge),
+            gauge,
+        }
+    }
+}
+
+/// Track a new guard for `gauge`, reporting its name and labels, including constant labels.
+#[cfg(feature = "leak-detection")]
+fn track_gauge<P: Atomic>(gauge: &GenericGauge<P>) -> TrackedGuard {
+    use prometheus::core::{Collector, Metric};
+
+    let labels = gauge
+        .metric()
+        .get_label()
+        .iter()
+        .map(|pair| (pair.get_name().to_owned(), pair.get_value().to_owned()))
+        .collect();
+    TrackedGuard::new(&gauge.desc()[0].fq_name, labels)
+}
+
+/// The label names and values of `labels`.
+#[cfg(feature = "leak-detection")]
+fn label_pairs<L: Labels>(labels: &L) -> Vec<(String, String)> {
+    let names = L::label_names();
+    labels.visit_label_values(|vals| {
+        names
+            .iter()
+            .zip(vals)
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .collect()
+    })
+}
+
 /// An extension trait for [`GenericGauge`] to provide methods for temporarily modifying a gauge.
 pub trait GuardedGauge<P: Atomic + 'static> {
     /// Increase the gauge by 1 while the guard exists.
     #[must_use]
-    fn guarded_inc(&'static self) -> GenericGaugeGuard<P>;
+    fn guarded_inc(&self) -> GenericGaugeGuard<P, &GenericGauge<P>>;
+
+    /// Increase the gauge by the given increment while the guard exists.
+    #[must_use]
+    fn guarded_add(&self, v: P::T) -> GenericGaugeGuard<P, &GenericGauge<P>>;
+
+    /// Increase the gauge by 1 while the guard exists.
+    ///
+    /// The guard holds a clone of the [`Arc`], so it is not tied to the lifetime of `self`.
+    #[must_use]
+    fn guarded_inc_owned(self: &Arc<Self>) -> GenericGaugeGuard<P, Arc<GenericGauge<P>>>;
 
     /// Increase the gauge by the given increment while the guard exists.
+    ///
+    /// The guard holds a clone of the [`Arc`], so it is not tied to the lifetime of `self`.
     #[must_use]
-    fn guarded_add(&'static self, v: P::T) -> GenericGaugeGuard<P>;
+    fn guarded_add_owned(self: &Arc<Self>, v: P::T) -> GenericGaugeGuard<P, Arc<GenericGauge<P>>>;
+
+    /// Set the gauge to `v` while the guard exists, restoring its previous value afterwards.
+    ///
+    /// See [`GenericGaugeSetGuard`] for how nested guards are handled.
+    #[must_use]
+    fn guarded_set(&self, v: P::T) -> GenericGaugeSetGuard<P, &GenericGauge<P>>;
+
+    /// Set the gauge to `v` while the guard exists, restoring its previous value afterwards.
+    ///
+    /// The guard holds a clone of the [`Arc`], so it is not tied to the lifetime of `self`.
+    #[must_use]
+    fn guarded_set_owned(
+        self: &Arc<Self>,
+        v: P::T,
+    ) -> GenericGaugeSetGuard<P, Arc<GenericGauge<P>>>;
 }
 
 impl<P: Atomic + 'static> GuardedGauge<P> for GenericGauge<P> {
-    fn guarded_inc(&'static self) -> GenericGaugeGuard<P> {
-        self.inc();
-        GenericGaugeGuard {
-            value: <P::T as Number>::from_i64(1),
-            gauge: self,
+    fn guarded_inc(&self) -> GenericGaugeGuard<P, &GenericGauge<P>> {
+        GenericGaugeGuard::new(self, <P::T as Number>::from_i64(1))
+    }
+
+    fn guarded_add(&self, v: P::T) -> GenericGaugeGuard<P, &GenericGauge<P>> {
+        GenericGaugeGuard::new(self, v)
+    }
+
+    fn guarded_inc_owned(self: &Arc<Self>) -> GenericGaugeGuard<P, Arc<GenericGauge<P>>> {
+        GenericGaugeGuard::new(Arc::clone(self), <P::T as Number>::from_i64(1))
+    }
+
+    fn guarded_add_owned(self: &Arc<Self>, v: P::T) -> GenericGaugeGuard<P, Arc<GenericGauge<P>>> {
+        GenericGaugeGuard::new(Arc::clone(self), v)
+    }
+
+    fn guarded_set(&self, v: P::T) -> GenericGaugeSetGuard<P, &GenericGauge<P>> {
+        GenericGaugeSetGuard::new(self, v)
+    }
+
+    fn guarded_set_owned(
+        self: &Arc<Self>,
+        v: P::T,
+    ) -> GenericGaugeSetGuard<P, Arc<GenericGauge<P>>> {
+        GenericGaugeSetGuard::new(Arc::clone(self), v)
+    }
+}
+
+/// The restore stacks for the gauge series in one shard of [`RESTORE_STACKS`].
+type RestoreShard = Mutex<HashMap<usize, Vec<RestoreEntry>>>;
+
+/// The number of independently locked shards that restore stacks are spread over.
+const RESTORE_SHARDS: usize = 16;
+
+lazy_static! {
+    /// The values to restore for every gauge series with active set guards, keyed by
+    /// [`series_key`] and spread over shards so that guards for unrelated gauges rarely contend.
+    /// Each stack is ordered by guard creation.
+    static ref RESTORE_STACKS: Vec<RestoreShard> =
+        (0..RESTORE_SHARDS).map(|_| Mutex::default()).collect();
+}
+
+/// The source of unique identifiers for set guards.
+static NEXT_SET_GUARD_ID: atomic::AtomicU64 = atomic::AtomicU64::new(0);
+
+/// An active set guard, and the value to restore when it is dropped.
+struct RestoreEntry {
+    id: u64,
+    /// The `P::T` that the gauge held when the guard was created.
+    previous: Box<dyn Any + Send>,
+    /// A clone of the `GenericGauge<P>`, which keeps its value cell, and so the key of this
+    /// stack, from being reused by another gauge while the entry exists.
+    _gauge: Box<dyn Any + Send>,
+}
+
+/// Identify the value cell that `gauge` updates by the address of its descriptor, which lives in
+/// the allocation shared by every clone of the gauge. Separately constructed gauges have separate
+/// cells, even if they have the same name and labels.
+fn series_key<P: Atomic>(gauge: &GenericGauge<P>) -> usize {
+    gauge.desc()[0] as *const Desc as usize
+}
+
+/// The shard holding the restore stack for `key`.
+fn restore_stacks(key: usize) -> &'static RestoreShard {
+    // The lowest bits of an address are fixed by alignment, so they are skipped.
+    &RESTORE_STACKS[(key >> 4) % RESTORE_SHARDS]
+}
+
+/// Set `gauge` to `value`, recording its previous value in the stack for `key`. Returns the
+/// identifier to pass to [`restore_gauge`].
+fn set_gauge<P: Atomic + 'static>(key: usize, gauge: &GenericGauge<P>, value: P::T) -> u64 {
+    let id = NEXT_SET_GUARD_ID.fetch_add(1, Ordering::Relaxed);
+    let mut stacks = restore_stacks(key).lock();
+    let previous = Box::new(gauge.get());
+    gauge.set(value);
+    stacks.entry(key).or_default().push(RestoreEntry {
+        id,
+        previous,
+        _gauge: Box::new(gauge.clone()),
+    });
+    id
+}
+
+/// Remove the guard `id` from the stack for `key`.
+///
+/// If it is the most recent guard, the gauge is restored to its previous value. Otherwise, the
+/// gauge keeps the value of the more recent guards, and the guard created after `id` inherits its
+/// previous value, as if the guards had been dropped in order.
+fn restore_gauge<P: Atomic + 'static>(key: usize, gauge: &GenericGauge<P>, id: u64) {
+    let mut stacks = restore_stacks(key).lock();
+    let stack = match stacks.get_mut(&key) {
+        Some(stack) => stack,
+        None => return,
+    };
+    if let Some(idx) = stack.iter().position(|entry| entry.id == id) {
+        let entry = stack.remove(idx);
+        match stack.get_mut(idx) {
+            Some(next) => next.previous = entry.previous,
+            None => match entry.previous.downcast_ref::<P::T>() {
+                Some(previous) => gauge.set(*previous),
+                // Stacks are keyed by a gauge's value cell, so this should be unreachable.
+                None => debug_assert!(false, "set guard restored a gauge of another value type"),
+            },
         }
     }
+    if stack.is_empty() {
+        stacks.remove(&key);
+    }
+}
+
+/// An RAII-style set guard for an [`AtomicI64`] gauge.
+///
+/// Created by the methods on the [`GuardedGauge`] extension trait.
+pub type IntGaugeSetGuard<G = &'static GenericGauge<AtomicI64>> =
+    GenericGaugeSetGuard<AtomicI64, G>;
+
+/// An RAII-style set guard for an [`AtomicF64`] gauge.
+///
+/// Created by the methods on the [`GuardedGauge`] extension trait.
+pub type GaugeSetGuard<G = &'static GenericGauge<AtomicF64>> = GenericGaugeSetGuard<AtomicF64, G>;
+
+/// An RAII-style guard that sets a gauge to a value, and restores the gauge's previous value
+/// when dropped.
+///
+/// Guards for the same gauge nest like a stack: the gauge holds the value of the most recently
+/// created guard that is still alive. Dropping guards out of order is handled as if they had been
+/// dropped in order, so the gauge returns to its original value once every guard is dropped.
+///
+/// Nesting is tracked by the gauge's value rather than by handle, so guards created through
+/// different clones of a [`GenericGauge`] nest with each other, while separately constructed
+/// gauges never do, even if they have the same name and labels. A guard that is leaked with
+/// [`std::mem::forget`] is treated as if it were still alive.
+///
+/// Created by the methods on the [`GuardedGauge`] extension trait.
+pub struct GenericGaugeSetGuard<
+    P: Atomic + 'static,
+    G: Deref<Target = GenericGauge<P>> = &'static GenericGauge<P>,
+> {
+    id: u64,
+    key: usize,
+    gauge: G,
+    #[cfg(feature = "leak-detection")]
+    _tracked: TrackedGuard,
+}
+
+impl<P: Atomic + 'static, G: Deref<Target = GenericGauge<P>>> GenericGaugeSetGuard<P, G> {
+    /// Set the gauge, returning a guard that will restore its previous value.
+    fn new(gauge: G, value: P::T) -> Self {
+        let key = series_key(&gauge);
+        let id = set_gauge(key, &gauge, value);
+        Self {
+            id,
+            key,
+            #[cfg(feature = "leak-detection")]
+            _tracked: track_gauge(&gauge),
+            gauge,
+        }
+    }
+}
+
+/// When a set guard is dropped, it will restore the gauge's previous value.
+impl<P: Atomic + 'static, G: Deref<Target = GenericGauge<P>>> Drop for GenericGaugeSetGuard<P, G> {
+    fn drop(&mut self) {
+        restore_gauge(self.key, &self.gauge, self.id);
+    }
+}
+
+/// An RAII-style guard for a labeled [`AtomicI64`] gauge.
+///
+/// Created by the methods on [`IntGaugeWithLabels`][crate::IntGaugeWithLabels].
+pub type IntGaugeGuardWithLabels<'a, L> = GenericGaugeGuardWithLabels<'a, AtomicI64, L>;
+
+/// An RAII-style guard for a labeled [`AtomicF64`] gauge.
+///
+/// Created by the methods on [`GaugeWithLabels`][crate::GaugeWithLabels].
+pub type GaugeGuardWithLabels<'a, L> = GenericGaugeGuardWithLabels<'a, AtomicF64, L>;
+
+/// An RAII-style guard for a labeled gauge, ensuring that an increment of the gauge for some set
+/// of labels always has a corresponding decrement.
+///
+/// The series for the labels is looked up once, when the guard is created or its labels change,
+/// and the decrement is applied to that same series. This keeps the increment and decrement
+/// together even if a cardinality limit or idle expiry would resolve the labels differently later.
+/// Series with live guards do not expire.
+///
+/// Created by calling [`GenericGaugeWithLabels::guarded_inc`] or
+/// [`GenericGaugeWithLabels::guarded_add`].
+pub struct GenericGaugeGuardWithLabels<'a, P: Atomic + 'static, L: Labels> {
+    value: P::T,
+    metric: &'a GenericGaugeWithLabels<P, L>,
+    labels: L,
+    /// The series that was increased, and the key that keeps it from expiring.
+    series: (GenericGauge<P>, Option<u64>),
+    #[cfg(feature = "leak-detection")]
+    tracked: TrackedGuard,
+}
+
+/// When a labeled gauge guard is dropped, it will perform the corresponding decrement.
+impl<'a, P: Atomic + 'static, L: Labels> Drop for GenericGaugeGuardWithLabels<'a, P, L> {
+    fn drop(&mut self) {
+        let (gauge, pin) = &self.series;
+        gauge.sub(self.value);
+        self.metric.unpin_series(*pin);
+    }
+}
+
+impl<'a, P: Atomic + 'static, L: Labels> GenericGaugeGuardWithLabels<'a, P, L> {
+    /// Increase the gauge, returning a guard that will perform the corresponding decrement.
+    //
+    // This is not exposed in the public interface, these should only be acquired through
+    // `guarded_inc` or `guarded_add`.
+    pub(crate) fn new(metric: &'a GenericGaugeWithLabels<P, L>, value: P::T, labels: L) -> Self {
+        let series = metric.pin_series(&labels);
+        series.0.add(value);
+        Self {
+            value,
+            metric,
+            series,
+            #[cfg(feature = "leak-detection")]
+            tracked: TrackedGuard::new(metric.name(), label_pairs(&labels)),
+            labels,
+        }
+    }
+
+    /// Move the increment to a new set of labels.
+    ///
+    /// The gauge is decreased for the current labels and increased for `new_labels`, and
+    /// `new_labels` will be used for the decrement once the guard is dropped.
+    pub fn with_labels(mut self, new_labels: L) -> GenericGaugeGuardWithLabels<'a, P, L> {
+        self.set_labels(new_labels);
+        self
+    }
+
+    /// Move the increment to a new set of labels, without consuming the guard.
+    ///
+    /// See [`GenericGaugeGuardWithLabels::with_labels`].
+    pub fn set_labels(&mut self, new_labels: L) {
+        self.labels = new_labels;
+        self.move_increment();
+    }
+
+    /// Reference to the labels that will be used when decrementing the gauge.
+    pub fn labels(&self) -> &L {
+        &self.labels
+    }
+
+    /// Mutable reference to the labels that will be used when decrementing the gauge.
+    ///
+    /// The increment is moved to the updated labels once the returned [`GaugeGuardLabelsMut`] is
+    /// dropped, as with [`GenericGaugeGuardWithLabels::set_labels`].
+    pub fn labels_mut(&mut self) -> GaugeGuardLabelsMut<'_, 'a, P, L> {
+        GaugeGuardLabelsMut { guard: self }
+    }
+
+    /// Move the increment from the series it was applied to, to the series for the current labels.
+    fn move_increment(&mut self) {
+        let series = self.metric.pin_series(&self.labels);
+        series.0.add(self.value);
+        let (gauge, pin) = std::mem::replace(&mut self.series, series);
+        gauge.sub(self.value);
+        self.metric.unpin_series(pin);
+        #[cfg(feature = "leak-detection")]
+        self.tracked.set_labels(label_pairs(&self.labels));
+    }
+}
+
+/// A mutable reference to the labels of a [`GenericGaugeGuardWithLabels`], which moves the
+/// guard's increment to the updated labels when dropped.
+///
+/// Created by calling [`GenericGaugeGuardWithLabels::labels_mut`].
+pub struct GaugeGuardLabelsMut<'g, 'a, P: Atomic + 'static, L: Labels> {
+    guard: &'g mut GenericGaugeGuardWithLabels<'a, P, L>,
+}
+
+impl<'g, 'a, P: Atomic + 'static, L: Labels> Deref for GaugeGuardLabelsMut<'g, 'a, P, L> {
+    type Target = L;
+
+    fn deref(&self) -> &L {
+        &self.guard.labels
+    }
+}
+
+impl<'g, 'a, P: Atomic + 'static, L: Labels> DerefMut for GaugeGuardLabelsMut<'g, 'a, P, L> {
+    fn deref_mut(&mut self) -> &mut L {
+        &mut self.guard.labels
+    }
+}
+
+impl<'g, 'a, P: Atomic + 'static, L: Labels> Drop for GaugeGuardLabelsMut<'g, 'a, P, L> {
+    fn drop(&mut self) {
+        self.guard.move_increment();
+    }
+}
+
+/// An RAII-style set guard for a labeled [`AtomicI64`] gauge.
+///
+/// Created by calling [`IntGaugeWithLabels::guarded_set`][crate::IntGaugeWithLabels].
+pub type IntGaugeSetGuardWithLabels<'a, L> = GenericGaugeSetGuardWithLabels<'a, AtomicI64, L>;
+
+/// An RAII-style set guard for a labeled [`AtomicF64`] gauge.
+///
+/// Created by calling [`GaugeWithLabels::guarded_set`][crate::GaugeWithLabels].
+pub type GaugeSetGuardWithLabels<'a, L> = GenericGaugeSetGuardWithLabels<'a, AtomicF64, L>;
+
+/// An RAII-style guard that sets a labeled gauge to a value for some set of labels, and restores
+/// the previous value for those labels when dropped.
+///
+/// Guards for the same labels nest like a stack, as described for [`GenericGaugeSetGuard`]. The
+/// series for the labels is looked up once, when the guard is created, and its previous value is
+/// restored to that same series. Series with live guards do not expire.
+///
+/// Created by calling [`GenericGaugeWithLabels::guarded_set`].
+pub struct GenericGaugeSetGuardWithLabels<'a, P: Atomic + 'static, L: Labels> {
+    id: u64,
+    key: usize,
+    metric: &'a GenericGaugeWithLabels<P, L>,
+    labels: L,
+    /// The series that was set, and the key that keeps it from expiring.
+    series: (GenericGauge<P>, Option<u64>),
+    #[cfg(feature = "leak-detection")]
+    _tracked: TrackedGuard,
+}
 
-    fn guarded_add(&'static self, v: P::T) -> GenericGaugeGuard<P> {
-        self.add(v);
-        GenericGaugeGuard {
-            value: v,
-            gauge: self,
+impl<'a, P: Atomic + 'static, L: Labels> GenericGaugeSetGuardWithLabels<'a, P, L> {
+    /// Set the gauge, returning a guard that will restore its previous value.
+    //
+    // This is not exposed in the public interface, these should only be acquired through
+    // `guarded_set`.
+    pub(crate) fn new(metric: &'a GenericGaugeWithLabels<P, L>, value: P::T, labels: L) -> Self {
+        let series = metric.pin_series(&labels);
+        let key = series_key(&series.0);
+        let id = set_gauge(key, &series.0, value);
+        Self {
+            id,
+            key,
+            metric,
+            #[cfg(feature = "leak-detection")]
+            _tracked: TrackedGuard::new(metric.name(), label_pairs(&labels)),
+            labels,
+            series,
         }
     }
+
+    /// Reference to the labels of the gauge that will be restored.
+    pub fn labels(&self) -> &L {
+        &self.labels
+    }
+}
+
+/// When a labeled set guard is dropped, it will restore the gauge's previous value.
+impl<'a, P: Atomic + 'static, L: Labels> Drop for GenericGaugeSetGuardWithLabels<'a, P, L> {
+    fn drop(&mut self) {
+        let (gauge, pin) = &self.series;
+        restore_gauge(self.key, gauge, self.id);
+        self.metric.unpin_series(*pin);
+    }
 }
 
 /// A guard that will automatically increment a labeled metric when dropped.
 ///
-/// Created by calling [`IntCounterWithLabels::deferred_inc`].
-pub struct DeferredAddWithLabels<'a, L: Labels> {
-    value: Option<u64>,
-    metric: &'a IntCounterWithLabels<L>,
+/// Created by calling [`GenericCounterWithLabels::deferred_inc`].
+pub struct DeferredAddWithLabels<'a, L: Labels, P: Atomic + 'static = AtomicU64> {
+    value: Option<P::T>,
+    metric: &'a GenericCounterWithLabels<P, L>,
     labels: L,
 }
 
 /// When dropped, a [`DeferredAddWithLabels`] guard will increment its counter.
-impl<'a, L: Labels> Drop for DeferredAddWithLabels<'a, L> {
+impl<'a, L: Labels, P: Atomic + 'static> Drop for DeferredAddWithLabels<'a, L, P> {
     fn drop(&mut self) {
         if let Some(value) = self.value {
             self.metric.add(value, &self.labels)
@@ -74,12 +509,12 @@ impl<'a, L: Labels> Drop for DeferredAddWithLabels<'a, L> {
     }
 }
 
-impl<'a, L: Labels> DeferredAddWithLabels<'a, L> {
+impl<'a, L: Labels, P: Atomic + 'static> DeferredAddWithLabels<'a, L, P> {
     /// Create a new deferred increment guard.
     //
     // This is not exposed in the public interface, these should only be acquired through
     // `deferred_inc`.
-    pub(crate) fn new(metric: &'a IntCounterWithLabels<L>, value: u64, labels: L) -> Self {
+    pub(crate) fn new(metric: &'a GenericCounterWithLabels<P, L>, value: P::T, labels: L) -> Self {
         Self {
             value: Some(value),
             metric,
@@ -88,7 +523,7 @@ impl<'a, L: Labels> DeferredAddWithLabels<'a, L> {
     }
 
     /// Update the labels to use when incrementing the metric.
-    pub fn with_labels(mut self, new_labels: L) -> DeferredAddWithLabels<'a, L> {
+    pub fn with_labels(mut self, new_labels: L) -> DeferredAddWithLabels<'a, L, P> {
         self.labels = new_labels;
         self
     }
@@ -114,15 +549,169 @@ impl<'a, L: Labels> DeferredAddWithLabels<'a, L> {
     }
 }
 
+crate::label_enum! {
+    /// How a guarded operation ended, as reported by [`DeferredAddWithOutcome`] guards and
+    /// [`Completion::outcome`][crate::Completion::outcome].
+    #[label = "outcome"]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum Outcome {
+        /// The guard was explicitly completed.
+        Success,
+        /// The guard was dropped without being completed, e.g. after an early return.
+        Cancelled,
+        /// The guard was dropped while the thread was panicking.
+        Panicked,
+    }
+}
+
+/// A guard that will automatically increment a labeled metric when dropped, adding an
+/// [`Outcome`] label that records how the guarded operation ended.
+///
+/// The outcome is [`Outcome::Success`] if the guard is completed with
+/// [`DeferredAddWithOutcome::complete`], [`Outcome::Panicked`] if it is dropped during unwinding,
+/// and [`Outcome::Cancelled`] if it is dropped otherwise.
+///
+/// Created by calling [`GenericCounterWithLabels::deferred_inc_with_outcome`].
+pub struct DeferredAddWithOutcome<'a, L: Labels, P: Atomic + 'static = AtomicU64> {
+    value: P::T,
+    metric: &'a GenericCounterWithLabels<P, (L, Outcome)>,
+    labels: (L, Outcome),
+}
+
+/// When dropped, a [`DeferredAddWithOutcome`] guard will increment its counter.
+impl<'a, L: Labels, P: Atomic + 'static> Drop for DeferredAddWithOutcome<'a, L, P> {
+    fn drop(&mut self) {
+        if self.labels.1 != Outcome::Success && std::thread::panicking() {
+            self.labels.1 = Outcome::Panicked;
+        }
+        self.metric.add(self.value, &self.labels)
+    }
+}
+
+impl<'a, L: Labels, P: Atomic + 'static> DeferredAddWithOutcome<'a, L, P> {
+    /// Create a new deferred increment guard.
+    //
+    // This is not exposed in the public interface, these should only be acquired through
+    // `deferred_inc_with_outcome`.
+    pub(crate) fn new(
+        metric: &'a GenericCounterWithLabels<P, (L, Outcome)>,
+        value: P::T,
+        labels: L,
+    ) -> Self {
+        Self {
+            value,
+            metric,
+            labels: (labels, Outcome::Cancelled),
+        }
+    }
+
+    /// Update the labels to use when incrementing the metric.
+    pub fn with_labels(mut self, new_labels: L) -> DeferredAddWithOutcome<'a, L, P> {
+        self.labels.0 = new_labels;
+        self
+    }
+
+    /// Reference to the labels that will be used when incrementing the metric.
+    pub fn labels(&self) -> &L {
+        &self.labels.0
+    }
+
+    /// Mutable reference to the labels that will be used when incrementing the metric.
+    pub fn labels_mut(&mut self) -> &mut L {
+        &mut self.labels.0
+    }
+
+    /// Perform the increment with a [`Outcome::Success`] outcome, consuming the guard.
+    pub fn complete(mut self) {
+        self.labels.1 = Outcome::Success;
+    }
+}
+
+/// A guard that will automatically observe the elapsed time into a labeled histogram when dropped.
+///
+/// Unlike a [`HistogramTimer`][prometheus::HistogramTimer], the labels are only read when the
+/// observation is made, so they can be decided once the timed operation has finished.
+///
+/// Created by calling [`HistogramWithLabels::start_deferred_timer`].
+pub struct DeferredObserveWithLabels<'a, L: Labels> {
+    start: Option<Instant>,
+    metric: &'a HistogramWithLabels<L>,
+    labels: L,
+}
+
+/// When dropped, a [`DeferredObserveWithLabels`] guard will observe the elapsed time, in seconds.
+impl<'a, L: Labels> Drop for DeferredObserveWithLabels<'a, L> {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            self.metric
+                .observe(&self.labels, start.elapsed().as_secs_f64())
+        }
+    }
+}
+
+impl<'a, L: Labels> DeferredObserveWithLabels<'a, L> {
+    /// Create a new deferred observation guard, starting the timer.
+    //
+    // This is not exposed in the public interface, these should only be acquired through
+    // `start_deferred_timer`.
+    pub(crate) fn new(metric: &'a HistogramWithLabels<L>, labels: L) -> Self {
+        Self {
+            start: Some(Instant::now()),
+            metric,
+            labels,
+        }
+    }
+
+    /// Update the labels to use when observing the elapsed time.
+    pub fn with_labels(mut self, new_labels: L) -> DeferredObserveWithLabels<'a, L> {
+        self.labels = new_labels;
+        self
+    }
+
+    /// Reference to the labels that will be used when observing the elapsed time.
+    pub fn labels(&self) -> &L {
+        &self.labels
+    }
+
+    /// Mutable reference to the labels that will be used when observing the elapsed time.
+    pub fn labels_mut(&mut self) -> &mut L {
+        &mut self.labels
+    }
+
+    /// Eagerly observe the elapsed time, consuming the guard.
+    pub fn complete_observe(self) {
+        drop(self)
+    }
+
+    /// Cancel the observation, consuming the guard.
+    pub fn cancel(&mut self) {
+        self.start = None;
+    }
+}
+
 /// A guard that will automatically increment a [`GenericCounter`] when dropped.
 ///
+/// The guard holds on to the counter through `C`, which is a reference to the counter for guards
+/// created with [`DeferredCounter::deferred_add`], and an [`Arc`] for guards created with
+/// [`DeferredCounter::deferred_add_owned`].
+///
 /// Created by the methods on the [`DeferredCounter`] extension trait.
-pub struct DeferredAdd<'a, P: Atomic> {
+pub struct DeferredAdd<'a, P: Atomic, C: Deref<Target = GenericCounter<P>> = &'a GenericCounter<P>>
+{
     value: Option<P::T>,
-    metric: &'a GenericCounter<P>,
+    metric: C,
+    _lifetime: PhantomData<&'a ()>,
 }
 
-impl<'a, P: Atomic> DeferredAdd<'a, P> {
+impl<'a, P: Atomic, C: Deref<Target = GenericCounter<P>>> DeferredAdd<'a, P, C> {
+    fn new(metric: C, value: P::T) -> Self {
+        Self {
+            value: Some(value),
+            metric,
+            _lifetime: PhantomData,
+        }
+    }
+
     /// Eagerly perform the increment, consuming the guard.
     pub fn complete_add(self) {
         drop(self)
@@ -135,7 +724,7 @@ impl<'a, P: Atomic> DeferredAdd<'a, P> {
 }
 
 /// When dropped, a [`DeferredAdd`] guard will increment its counter.
-impl<'a, P: Atomic> Drop for DeferredAdd<'a, P> {
+impl<'a, P: Atomic, C: Deref<Target = GenericCounter<P>>> Drop for DeferredAdd<'a, P, C> {
     fn drop(&mut self) {
         if let Some(value) = self.value {
             self.metric.inc_by(value);
@@ -148,20 +737,153 @@ impl<'a, P: Atomic> Drop for DeferredAdd<'a, P> {
 pub trait DeferredCounter<P: Atomic + 'static> {
     /// Increase the counter by `1` when the guard is dropped.
     #[must_use]
-    fn deferred_inc(&'static self) -> DeferredAdd<P> {
+    fn deferred_inc(&self) -> DeferredAdd<'_, P> {
         self.deferred_add(<P::T as Number>::from_i64(1))
     }
 
     /// Increase the counter by `v` when the guard is dropped.
     #[must_use]
-    fn deferred_add(&'static self, v: P::T) -> DeferredAdd<P>;
+    fn deferred_add(&self, v: P::T) -> DeferredAdd<'_, P>;
+
+    /// Increase the counter by `1` when the guard is dropped.
+    ///
+    /// The guard holds a clone of the [`Arc`], so it is not tied to the lifetime of `self`.
+    #[must_use]
+    fn deferred_inc_owned(self: &Arc<Self>) -> DeferredAdd<'static, P, Arc<GenericCounter<P>>> {
+        self.deferred_add_owned(<P::T as Number>::from_i64(1))
+    }
+
+    /// Increase the counter by `v` when the guard is dropped.
+    ///
+    /// The guard holds a clone of the [`Arc`], so it is not tied to the lifetime of `self`.
+    #[must_use]
+    fn deferred_add_owned(
+        self: &Arc<Self>,
+        v: P::T,
+    ) -> DeferredAdd<'static, P, Arc<GenericCounter<P>>>;
 }
 
 impl<P: Atomic + 'static> DeferredCounter<P> for GenericCounter<P> {
-    fn deferred_add(&'static self, v: P::T) -> DeferredAdd<P> {
-        DeferredAdd {
-            value: Some(v),
-            metric: self,
+    fn deferred_add(&self, v: P::T) -> DeferredAdd<'_, P> {
+        DeferredAdd::new(self, v)
+    }
+
+    fn deferred_add_owned(
+        self: &Arc<Self>,
+        v: P::T,
+    ) -> DeferredAdd<'static, P, Arc<GenericCounter<P>>> {
+        DeferredAdd::new(Arc::clone(self), v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeferredCounter, GuardedGauge};
+    use crate::IntGaugeWithLabels;
+    use prometheus::{IntCounter, IntGauge};
+    use std::sync::Arc;
+
+    #[test]
+    fn guards_borrow_local_metrics() {
+        let gauge = IntGauge::new("local_gauge", "help").unwrap();
+        let counter = IntCounter::new("local_counter", "help").unwrap();
+        {
+            let _gauge_guard = gauge.guarded_add(2);
+            let _counter_guard = counter.deferred_inc();
+            assert_eq!(gauge.get(), 2);
+            assert_eq!(counter.get(), 0);
+        }
+        assert_eq!(gauge.get(), 0);
+        assert_eq!(counter.get(), 1);
+    }
+
+    #[test]
+    fn owned_guards_outlive_their_handles() {
+        let gauge = Arc::new(IntGauge::new("owned_gauge", "help").unwrap());
+        let counter = Arc::new(IntCounter::new("owned_counter", "help").unwrap());
+        // Clones of a metric share its value, so these observe the guards' effects.
+        let (gauge_value, counter_value) = ((*gauge).clone(), (*counter).clone());
+
+        let gauge_guard = gauge.guarded_inc_owned();
+        let counter_guard = counter.deferred_add_owned(3);
+        drop((gauge, counter));
+        assert_eq!(gauge_value.get(), 1);
+
+        std::thread::spawn(move || drop((gauge_guard, counter_guard)))
+            .join()
+            .unwrap();
+        assert_eq!(gauge_value.get(), 0);
+        assert_eq!(counter_value.get(), 3);
+    }
+
+    #[test]
+    fn set_guards_restore_values_stack_style() {
+        let gauge = IntGauge::new("generation", "help").unwrap();
+        gauge.set(7);
+
+        let outer = gauge.guarded_set(1);
+        let inner = gauge.guarded_set(2);
+        assert_eq!(gauge.get(), 2);
+        drop(inner);
+        assert_eq!(gauge.get(), 1);
+
+        let inner = gauge.guarded_set(3);
+        // Dropping the outer guard first keeps the inner guard's value in place...
+        drop(outer);
+        assert_eq!(gauge.get(), 3);
+        // ...and the inner guard then restores the value from before the outer guard.
+        drop(inner);
+        assert_eq!(gauge.get(), 7);
+    }
+
+    #[test]
+    fn set_guards_nest_across_clones_of_a_gauge() {
+        let gauge = IntGauge::new("replica", "help").unwrap();
+        let clone = gauge.clone();
+
+        let outer = gauge.guarded_set(1);
+        let inner = clone.guarded_set(2);
+        drop(outer);
+        assert_eq!(gauge.get(), 2);
+        drop(inner);
+        assert_eq!(clone.get(), 0);
+    }
+
+    #[test]
+    fn set_guards_do_not_nest_across_gauges_with_the_same_name() {
+        let a = IntGauge::new("maintenance_mode", "help").unwrap();
+        let b = IntGauge::new("maintenance_mode", "help").unwrap();
+
+        let guard_a = a.guarded_set(1);
+        let guard_b = b.guarded_set(5);
+        drop(guard_a);
+        assert_eq!((a.get(), b.get()), (0, 5));
+        drop(guard_b);
+        assert_eq!((a.get(), b.get()), (0, 0));
+    }
+
+    #[test]
+    fn labeled_set_guards_restore_values_per_label_set() {
+        crate::label_enum! {
+            #[label = "mode"]
+            enum Mode {
+                Maintenance,
+                Degraded,
+            }
         }
+
+        let gauge = IntGaugeWithLabels::<Mode>::new("mode", "help").unwrap();
+        let value = |mode| gauge.handle(&mode).get();
+
+        let maintenance = gauge.guarded_set(Mode::Maintenance, 1);
+        let degraded = gauge.guarded_set(Mode::Degraded, 1);
+        let nested = gauge.guarded_set(Mode::Maintenance, 2);
+        assert_eq!((value(Mode::Maintenance), value(Mode::Degraded)), (2, 1));
+
+        drop(maintenance);
+        drop(degraded);
+        assert_eq!((value(Mode::Maintenance), value(Mode::Degraded)), (2, 0));
+        drop(nested);
+        assert_eq!(value(Mode::Maintenance), 0);
     }
 }
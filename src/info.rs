@@ -0,0 +1,160 @@
+//! Info metrics, which export a constant value labeled with information about a process.
+
+use crate::{error::Result, state::ConsistentCollector, IntGaugeWithLabels, Labels};
+use parking_lot::{Mutex, RwLock};
+use prometheus::Registry;
+use std::sync::Arc;
+
+/// A metric exporting information as labels on a constant `1` value, like an OpenMetrics `Info`
+/// metric such as `build_info`.
+///
+/// Exactly one label set, provided when the metric is created, is exported at a time. It can be
+/// replaced with [`InfoMetric::set_labels`], for example when configuration is reloaded. Replacing
+/// the labels is atomic with respect to scrapes, which always see exactly one label set.
+///
+/// # Examples
+///
+/// ```
+/// use prometheus_utils::{InfoMetric, LabelValues, Labels};
+///
+/// struct BuildInfo {
+///     version: &'static str,
+///     commit: &'static str,
+/// }
+///
+/// impl Labels for BuildInfo {
+///     fn label_names() -> Vec<&'static str> {
+///         vec!["version", "commit"]
+///     }
+///
+///     fn possible_label_values() -> Vec<LabelValues<'static>> {
+///         vec![]
+///     }
+///
+///     fn label_values(&self) -> LabelValues<'_> {
+///         vec![self.version, self.commit]
+///     }
+/// }
+///
+/// let info = BuildInfo {
+///     version: env!("CARGO_PKG_VERSION"),
+///     commit: "0123abc",
+/// };
+/// let build_info = InfoMetric::new("build_info", "build information", info).unwrap();
+/// ```
+pub struct InfoMetric<L: Labels> {
+    gauge: IntGaugeWithLabels<L>,
+    labels: Mutex<L>,
+    updates: Arc<RwLock<()>>,
+}
+
+impl<L: Labels> InfoMetric<L> {
+    /// Construct a new info metric exporting `labels`, without registering it.
+    pub fn new(name: &str, help: &str, labels: L) -> Result<Self> {
+        let gauge = IntGaugeWithLabels::new(name, help)?;
+        gauge.set(&labels, 1);
+        Ok(Self {
+            gauge,
+            labels: Mutex::new(labels),
+            updates: Arc::default(),
+        })
+    }
+
+    /// Construct a new info metric exporting `labels` and register it in `registry`.
+    pub fn try_register_in(registry: &Registry, name: &str, help: &str, labels: L) -> Result<Self> {
+        let info = Self::new(name, help, labels)?;
+        info.register_in(registry)?;
+        Ok(info)
+    }
+
+    /// Construct and immediately register a new info metric exporting `labels`.
+    ///
+    /// The metric is registered in the default registry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric cannot be constructed or registered. See
+    /// [`InfoMetric::try_register_in`] for a fallible alternative.
+    pub fn register_new(name: &str, help: &str, labels: L) -> Self {
+        Self::try_register_in(prometheus::default_registry(), name, help, labels).unwrap()
+    }
+
+    /// Register this info metric in `registry`.
+    pub fn register_in(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(ConsistentCollector {
+            collector: self.gauge.collector(),
+            updates: self.updates.clone(),
+        }))?;
+        Ok(())
+    }
+
+    /// Export `labels` instead of the current label set.
+    pub fn set_labels(&self, labels: L) {
+        let mut current = self.labels.lock();
+        let _update = self.updates.read();
+        self.gauge.remove(&current);
+        self.gauge.set(&labels, 1);
+        *current = labels;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InfoMetric;
+    use prometheus::Registry;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    crate::label_enum! {
+        #[label = "mode"]
+        enum Mode {
+            Primary,
+            Replica,
+        }
+    }
+
+    #[test]
+    fn only_the_current_labels_are_exported() {
+        let registry = Registry::new();
+        let info =
+            InfoMetric::try_register_in(&registry, "mode_info", "help", Mode::Primary).unwrap();
+        let exported = || {
+            let families = registry.gather();
+            families[0]
+                .get_metric()
+                .iter()
+                .map(|m| {
+                    (
+                        m.get_label()[0].get_value().to_owned(),
+                        m.get_gauge().get_value(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(exported(), [("primary".to_owned(), 1.0)]);
+
+        info.set_labels(Mode::Replica);
+        assert_eq!(exported(), [("replica".to_owned(), 1.0)]);
+    }
+
+    #[test]
+    fn scrapes_never_see_half_replaced_labels() {
+        let registry = Registry::new();
+        let info =
+            InfoMetric::try_register_in(&registry, "role_info", "help", Mode::Primary).unwrap();
+        let exported = || registry.gather()[0].get_metric().len();
+
+        let scraping = AtomicBool::new(true);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                while scraping.load(Ordering::Relaxed) {
+                    info.set_labels(Mode::Replica);
+                    info.set_labels(Mode::Primary);
+                }
+            });
+            for _ in 0..1000 {
+                assert_eq!(exported(), 1);
+            }
+            scraping.store(false, Ordering::Relaxed);
+        });
+    }
+}
@@ -6,10 +6,172 @@
 //  /is/ their drop implementations.
 #![allow(dyn_drop)]
 
-use super::{GuardedGauge, IntCounterWithLabels, Labels};
+use super::{GenericCounterWithLabels, GuardedGauge, HistogramWithLabels, Labels, Outcome};
 use pin_project::pin_project;
-use prometheus::core::{Atomic, GenericCounter};
-use std::{any::Any, future, ops::Deref, pin::Pin, task};
+use prometheus::{
+    core::{Atomic, GenericCounter},
+    Histogram, IntCounter,
+};
+use std::{
+    any::Any,
+    future,
+    ops::Deref,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task,
+    time::{Duration, Instant},
+};
+
+/// A closure run before the first poll of an [`InstrumentedFuture`], optionally returning a
+/// resource guard to hold until the future resolves.
+type PrePoll = Box<dyn FnOnce() -> Option<Box<dyn Any + Send>> + Send>;
+
+/// A closure run with the output of an [`InstrumentedFuture`] once it resolves.
+type OnReady<T> = Box<dyn FnOnce(&T) + Send>;
+
+/// A closure observing how long an [`InstrumentedFuture`] took to resolve, in seconds.
+type Timer = Box<dyn FnOnce(f64) + Send>;
+
+/// An error type that can be described by Prometheus labels.
+///
+/// Error enums can implement this trait to map each of their variants to a label, typically a
+/// variant of an enum declared with [`label_enum!`], so that errors returned by futures can be
+/// counted with [`InstrumentedFuture::with_error_count`].
+///
+/// [`label_enum!`]: macro.label_enum.html
+///
+/// # Examples
+///
+/// ```
+/// use prometheus_utils::{label_enum, ErrorLabel};
+///
+/// label_enum! {
+///     #[label = "error"]
+///     enum FetchErrorLabel {
+///         Timeout,
+///         NotFound,
+///     }
+/// }
+///
+/// enum FetchError {
+///     Timeout(std::time::Duration),
+///     NotFound { key: String },
+/// }
+///
+/// impl ErrorLabel for FetchError {
+///     type Label = FetchErrorLabel;
+///
+///     fn error_label(&self) -> FetchErrorLabel {
+///         match self {
+///             FetchError::Timeout(_) => FetchErrorLabel::Timeout,
+///             FetchError::NotFound { .. } => FetchErrorLabel::NotFound,
+///         }
+///     }
+/// }
+/// ```
+pub trait ErrorLabel {
+    /// The labels describing this error.
+    type Label: Labels;
+
+    /// The labels to use when counting this error.
+    fn error_label(&self) -> Self::Label;
+}
+
+/// Metrics describing the individual polls of an [`InstrumentedFuture`], and the statistics they
+/// are computed from.
+#[derive(Default)]
+struct PollMetrics {
+    /// Histograms observing the total time spent polling the inner future, in seconds.
+    busy_time: Vec<Histogram>,
+    /// Histograms observing the number of times the inner future was polled.
+    poll_count: Vec<Histogram>,
+    /// Histograms observing the duration of the longest poll of the inner future, in seconds.
+    longest_poll: Vec<Histogram>,
+    /// Counters incremented for each poll of the inner future taking longer than the threshold.
+    slow_polls: Vec<(Duration, IntCounter)>,
+    busy: Duration,
+    polls: u64,
+    longest: Duration,
+}
+
+impl PollMetrics {
+    /// Record a poll of the inner future that took `duration`.
+    fn record(&mut self, duration: Duration) {
+        self.busy += duration;
+        self.polls += 1;
+        self.longest = self.longest.max(duration);
+        for (threshold, counter) in &self.slow_polls {
+            if duration > *threshold {
+                counter.inc();
+            }
+        }
+    }
+
+    /// Report the statistics for every poll of the inner future, once it has resolved.
+    fn report(&mut self) {
+        for histogram in self.busy_time.drain(..) {
+            histogram.observe(self.busy.as_secs_f64());
+        }
+        for histogram in self.poll_count.drain(..) {
+            histogram.observe(self.polls as f64);
+        }
+        for histogram in self.longest_poll.drain(..) {
+            histogram.observe(self.longest.as_secs_f64());
+        }
+    }
+}
+
+/// A handle for learning whether an [`InstrumentedFuture`] resolved, or was dropped before it
+/// could.
+///
+/// Passed to the closures given to [`InstrumentedFuture::with_completion_guard`], so that guards
+/// can check the outcome of the future when they are dropped.
+#[derive(Clone, Debug)]
+pub struct Completion(Arc<AtomicBool>);
+
+impl Completion {
+    /// Whether the future has resolved.
+    pub fn is_completed(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// The outcome of the future.
+    ///
+    /// This is [`Outcome::Success`] once the future has resolved. Before then, it is
+    /// [`Outcome::Panicked`] while the thread is panicking, for example because polling the
+    /// future panicked, and [`Outcome::Cancelled`] otherwise, which is the outcome when the
+    /// future is dropped unfinished.
+    pub fn outcome(&self) -> Outcome {
+        if self.is_completed() {
+            Outcome::Success
+        } else if std::thread::panicking() {
+            Outcome::Panicked
+        } else {
+            Outcome::Cancelled
+        }
+    }
+}
+
+/// A resource guard that increments a counter if the future it is attached to is dropped
+/// unfinished.
+struct CancelGuard {
+    /// Increments the counter. This is only taken on drop.
+    inc: Option<Box<dyn FnOnce() + Send>>,
+    completion: Completion,
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if !self.completion.is_completed() {
+            if let Some(inc) = self.inc.take() {
+                inc();
+            }
+        }
+    }
+}
 
 /// An instrumented [`Future`][std-future].
 ///
@@ -64,7 +226,7 @@ pub struct InstrumentedFuture<F: future::Future> {
     ///
     /// In practice, this holds a list of Prometheus counters or gauges to increment when the inner
     /// `Future` starts, returning a list of guards to decrement once the inner `Future` completes.
-    pre_polls: Vec<Box<dyn FnOnce() -> Option<Box<dyn Any + Send>> + Send>>,
+    pre_polls: Vec<PrePoll>,
     /// RAII guards that will be dropped once the future has resolved.
     ///
     /// In practice, this is used to hold values like [`IntGaugeGuard`][int-guard] and
@@ -78,6 +240,20 @@ pub struct InstrumentedFuture<F: future::Future> {
     /// [inc-until]: struct.InstrumentedFuture.html#method.increment_until_resolved
     #[allow(dyn_drop)]
     resource_guards: Vec<Box<dyn Any + Send>>,
+    /// Histograms observing how long the future took to resolve, in seconds.
+    timers: Vec<Timer>,
+    /// When the future was constructed.
+    created: Instant,
+    /// When the future was first polled, if it has been.
+    first_polled: Option<Instant>,
+    /// Whether timers measure from `created`, rather than from `first_polled`.
+    time_from_construction: bool,
+    /// Metrics about the individual polls of the inner future, if any have been attached.
+    poll_metrics: Option<Box<PollMetrics>>,
+    /// Marked as completed once the inner future resolves, if any guards need to know.
+    completion: Option<Completion>,
+    /// Closures to call with the output of the inner `Future` once it resolves.
+    on_ready: Vec<OnReady<F::Output>>,
 }
 
 /// Convert a [`Future`][future::Future] into an instrumented future.
@@ -97,6 +273,13 @@ impl<F: future::Future> IntoInstrumentedFuture for F {
             inner: self,
             pre_polls: vec![],
             resource_guards: vec![],
+            timers: vec![],
+            created: Instant::now(),
+            first_polled: None,
+            time_from_construction: false,
+            poll_metrics: None,
+            completion: None,
+            on_ready: vec![],
         }
     }
 }
@@ -146,6 +329,80 @@ impl<F: future::Future> InstrumentedFuture<F> {
         self
     }
 
+    /// Queue `guard_fn` to execute when the future is polled, retaining the returned value until
+    /// the future completes or is dropped.
+    ///
+    /// This behaves like [`InstrumentedFuture::with_guard`], except that `guard_fn` is given a
+    /// [`Completion`] handle, which the returned guard can use to learn whether the future
+    /// resolved or was cancelled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use prometheus_utils::{Completion, IntoInstrumentedFuture, Outcome};
+    ///
+    /// struct LogOutcome(Completion);
+    ///
+    /// impl Drop for LogOutcome {
+    ///     fn drop(&mut self) {
+    ///         if self.0.outcome() == Outcome::Cancelled {
+    ///             println!("the request was abandoned");
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let request = async {}
+    ///     .into_instrumented_future()
+    ///     .with_completion_guard(|completion| Some(Box::new(LogOutcome(completion))));
+    /// ```
+    pub fn with_completion_guard<GuardFn>(mut self, guard_fn: GuardFn) -> Self
+    where
+        GuardFn: FnOnce(Completion) -> Option<Box<dyn Any + Send>> + Send + 'static,
+    {
+        let completion = self.completion();
+        self.pre_polls.push(Box::new(move || guard_fn(completion)));
+        self
+    }
+
+    /// Increment a Prometheus counter if this future is dropped before it resolves.
+    ///
+    /// This includes futures that are dropped without ever being polled, and futures that are
+    /// dropped because polling them panicked.
+    pub fn with_cancel_count<P: Atomic + 'static>(self, counter: &GenericCounter<P>) -> Self {
+        let counter = counter.clone();
+        self.with_cancel_guard(Box::new(move || counter.inc()))
+    }
+
+    /// Increment a labeled Prometheus counter, using the provided `labels`, if this future is
+    /// dropped before it resolves.
+    ///
+    /// The series for `labels` is looked up when the counter is incremented, as for any other
+    /// update of the counter. See [`InstrumentedFuture::with_cancel_count`].
+    pub fn with_cancel_count_labeled<C, P, L>(self, counter: &'static C, labels: L) -> Self
+    where
+        C: Deref<Target = GenericCounterWithLabels<P, L>> + Sync,
+        P: Atomic + 'static,
+        L: Labels + Send + 'static,
+    {
+        self.with_cancel_guard(Box::new(move || counter.inc(&labels)))
+    }
+
+    /// Call `inc` if this future is dropped before it resolves.
+    fn with_cancel_guard(mut self, inc: Box<dyn FnOnce() + Send>) -> Self {
+        let guard = CancelGuard {
+            inc: Some(inc),
+            completion: self.completion(),
+        };
+        self.resource_guards.push(Box::new(guard));
+        self
+    }
+
+    fn completion(&mut self) -> Completion {
+        self.completion
+            .get_or_insert_with(|| Completion(Arc::new(AtomicBool::new(false))))
+            .clone()
+    }
+
     /// Increment a Prometheus counter immediately.
     pub fn with_count<P: Atomic + 'static>(mut self, counter: &'static GenericCounter<P>) -> Self {
         self.pre_polls.push(Box::new(move || {
@@ -156,9 +413,10 @@ impl<F: future::Future> InstrumentedFuture<F> {
     }
 
     /// Increment a labeled Prometheus counter when the future is polled.
-    pub fn with_count_labeled<C, L>(mut self, counter: &'static C, labels: L) -> Self
+    pub fn with_count_labeled<C, P, L>(mut self, counter: &'static C, labels: L) -> Self
     where
-        C: Deref<Target = IntCounterWithLabels<L>> + Sync,
+        C: Deref<Target = GenericCounterWithLabels<P, L>> + Sync,
+        P: Atomic + 'static,
         L: Labels + Sync + Send + 'static,
     {
         self.pre_polls.push(Box::new(move || {
@@ -193,6 +451,172 @@ impl<F: future::Future> InstrumentedFuture<F> {
         }));
         self
     }
+
+    /// Increment a shared Prometheus gauge until this future has resolved.
+    ///
+    /// This behaves like [`InstrumentedFuture::with_count_gauge`], using the
+    /// [`GuardedGauge::guarded_inc_owned`] trait method, for gauges that are not stored in a
+    /// `static`.
+    pub fn with_count_gauge_owned<T, P>(mut self, gauge: Arc<T>) -> Self
+    where
+        T: GuardedGauge<P> + Send + Sync + 'static,
+        P: Atomic + 'static,
+    {
+        self.pre_polls
+            .push(Box::new(move || Some(Box::new(gauge.guarded_inc_owned()))));
+        self
+    }
+
+    /// Observe how long this future took to resolve in a Prometheus histogram, in seconds.
+    ///
+    /// By default, the duration is measured from the first time the future is polled until it
+    /// returns [`Poll::Ready`][task::Poll::Ready]. Use
+    /// [`InstrumentedFuture::time_from_construction`] to measure from when the future was
+    /// instrumented instead. Nothing is observed if the future is dropped before it resolves.
+    pub fn with_timer(mut self, histogram: &Histogram) -> Self {
+        let histogram = histogram.clone();
+        self.timers
+            .push(Box::new(move |elapsed| histogram.observe(elapsed)));
+        self
+    }
+
+    /// Observe how long this future took to resolve in a labeled Prometheus histogram, in
+    /// seconds, using the provided `labels`.
+    ///
+    /// The series for `labels` is looked up when the duration is observed, as for any other
+    /// update of the histogram. See [`InstrumentedFuture::with_timer`].
+    pub fn with_timer_labeled<H, L>(mut self, histogram: &'static H, labels: L) -> Self
+    where
+        H: Deref<Target = HistogramWithLabels<L>> + Sync,
+        L: Labels + Send + 'static,
+    {
+        self.timers
+            .push(Box::new(move |elapsed| histogram.observe(&labels, elapsed)));
+        self
+    }
+
+    /// Measure the durations observed by timers from when this future was instrumented, rather
+    /// than from when it was first polled, so that any scheduling delay is included.
+    ///
+    /// This applies to every timer attached with [`InstrumentedFuture::with_timer`] or
+    /// [`InstrumentedFuture::with_timer_labeled`].
+    pub fn time_from_construction(mut self) -> Self {
+        self.time_from_construction = true;
+        self
+    }
+
+    /// Observe the total time spent inside the inner future's [`Future::poll`][future::Future::poll]
+    /// in a Prometheus histogram, in seconds, once the future has resolved.
+    ///
+    /// Compared to a timer from [`InstrumentedFuture::with_timer`], this excludes the time the
+    /// future spent waiting to be woken, so it shows how long the future kept an executor thread
+    /// busy.
+    pub fn with_busy_time(mut self, histogram: &Histogram) -> Self {
+        self.poll_metrics().busy_time.push(histogram.clone());
+        self
+    }
+
+    /// Observe the number of times the inner future was polled in a Prometheus histogram, once
+    /// the future has resolved.
+    pub fn with_poll_count(mut self, histogram: &Histogram) -> Self {
+        self.poll_metrics().poll_count.push(histogram.clone());
+        self
+    }
+
+    /// Observe the duration of the longest single poll of the inner future in a Prometheus
+    /// histogram, in seconds, once the future has resolved.
+    pub fn with_longest_poll(mut self, histogram: &Histogram) -> Self {
+        self.poll_metrics().longest_poll.push(histogram.clone());
+        self
+    }
+
+    /// Increment a Prometheus counter for every poll of the inner future that takes longer than
+    /// `threshold`.
+    ///
+    /// Unlike the other poll metrics, the counter is incremented as soon as a slow poll returns,
+    /// whether or not the future goes on to resolve.
+    pub fn with_slow_poll_count(mut self, threshold: Duration, counter: &IntCounter) -> Self {
+        self.poll_metrics()
+            .slow_polls
+            .push((threshold, counter.clone()));
+        self
+    }
+
+    fn poll_metrics(&mut self) -> &mut PollMetrics {
+        self.poll_metrics.get_or_insert_with(Default::default)
+    }
+}
+
+impl<F, T, E> InstrumentedFuture<F>
+where
+    F: future::Future<Output = Result<T, E>>,
+{
+    /// Increment a labeled Prometheus counter once this future resolves, using labels computed
+    /// from its result by `label_fn`.
+    ///
+    /// Nothing is counted if the future is dropped before it resolves.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lazy_static::lazy_static;
+    /// use prometheus_utils::{label_enum, IntCounterWithLabels, IntoInstrumentedFuture};
+    ///
+    /// label_enum! {
+    ///     #[label = "result"]
+    ///     enum ResultLabel {
+    ///         Ok,
+    ///         Err,
+    ///     }
+    /// }
+    ///
+    /// lazy_static! {
+    ///     static ref REQUESTS: IntCounterWithLabels<ResultLabel> =
+    ///         IntCounterWithLabels::register_new("requests", "requests handled");
+    /// }
+    ///
+    /// async fn handle() -> Result<(), std::io::Error> {
+    ///     Ok(())
+    /// }
+    ///
+    /// let request = handle()
+    ///     .into_instrumented_future()
+    ///     .with_result_count(&REQUESTS, |result| match result {
+    ///         Ok(_) => ResultLabel::Ok,
+    ///         Err(_) => ResultLabel::Err,
+    ///     });
+    /// ```
+    pub fn with_result_count<C, P, L, LabelFn>(
+        mut self,
+        counter: &'static C,
+        label_fn: LabelFn,
+    ) -> Self
+    where
+        C: Deref<Target = GenericCounterWithLabels<P, L>> + Sync,
+        P: Atomic + 'static,
+        L: Labels,
+        LabelFn: FnOnce(&Result<T, E>) -> L + Send + 'static,
+    {
+        self.on_ready
+            .push(Box::new(move |result| counter.inc(&label_fn(result))));
+        self
+    }
+
+    /// Increment a labeled Prometheus counter if this future resolves to an error, using the
+    /// labels provided by the error's [`ErrorLabel`] implementation.
+    pub fn with_error_count<C, P>(mut self, counter: &'static C) -> Self
+    where
+        C: Deref<Target = GenericCounterWithLabels<P, E::Label>> + Sync,
+        P: Atomic + 'static,
+        E: ErrorLabel,
+    {
+        self.on_ready.push(Box::new(move |result| {
+            if let Err(err) = result {
+                counter.inc(&err.error_label());
+            }
+        }));
+        self
+    }
 }
 
 impl<F: future::Future> future::Future for InstrumentedFuture<F> {
@@ -205,19 +629,47 @@ impl<F: future::Future> future::Future for InstrumentedFuture<F> {
     fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
         use task::Poll::{Pending, Ready};
         let pin_projection = self.project();
+        let first_polled = *pin_projection.first_polled.get_or_insert_with(Instant::now);
         for pre_poll in pin_projection.pre_polls.drain(..) {
             if let Some(droppable) = pre_poll() {
                 pin_projection.resource_guards.push(droppable);
             }
         }
-        match pin_projection.inner.poll(cx) {
+        let poll = match pin_projection.poll_metrics {
+            Some(poll_metrics) => {
+                let start = Instant::now();
+                let poll = pin_projection.inner.poll(cx);
+                poll_metrics.record(start.elapsed());
+                poll
+            }
+            None => pin_projection.inner.poll(cx),
+        };
+        match poll {
             // The inner future is still pending...
             p @ Pending => p,
             // If we are here, the inner future resolved! Before returning we should drop any
             // resource guards that may have been attached to this future.
-            out @ Ready(_) => {
+            Ready(out) => {
+                for on_ready in pin_projection.on_ready.drain(..) {
+                    on_ready(&out);
+                }
+                let start = if *pin_projection.time_from_construction {
+                    *pin_projection.created
+                } else {
+                    first_polled
+                };
+                let elapsed = start.elapsed().as_secs_f64();
+                for timer in pin_projection.timers.drain(..) {
+                    timer(elapsed);
+                }
+                if let Some(poll_metrics) = pin_projection.poll_metrics {
+                    poll_metrics.report();
+                }
+                if let Some(completion) = pin_projection.completion {
+                    completion.0.store(true, Ordering::SeqCst);
+                }
                 pin_projection.resource_guards.clear();
-                out
+                Ready(out)
             }
         }
     }
@@ -286,3 +738,242 @@ fn counters_increment_only_when_futures_run() {
     // and confirm the mutex has been work'd
     assert_eq!(*work_stoppage.lock().unwrap(), 4);
 }
+
+#[test]
+fn owned_gauges_are_held_until_futures_resolve() {
+    use prometheus::IntGauge;
+
+    let gauge = Arc::new(IntGauge::new("owned_gauge", "help").unwrap());
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("can build runtime");
+    let handle = rt.spawn(
+        rx.into_instrumented_future()
+            .with_count_gauge_owned(Arc::clone(&gauge)),
+    );
+
+    rt.block_on(tokio::task::yield_now());
+    assert_eq!(gauge.get(), 1);
+
+    tx.send(()).unwrap();
+    rt.block_on(handle).unwrap().unwrap();
+    assert_eq!(gauge.get(), 0);
+}
+
+#[test]
+fn timers_observe_until_futures_resolve() {
+    use lazy_static::lazy_static;
+    use prometheus::histogram_opts;
+    use std::time::Duration;
+
+    crate::label_enum! {
+        #[label = "kind"]
+        enum Kind {
+            Sleep,
+        }
+    }
+
+    let from_poll = Histogram::with_opts(histogram_opts!("from_poll", "help")).unwrap();
+    let from_construction =
+        Histogram::with_opts(histogram_opts!("from_construction", "help")).unwrap();
+    lazy_static! {
+        static ref LABELED: HistogramWithLabels<Kind> =
+            HistogramWithLabels::new("labeled_timer", "help").unwrap();
+    }
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .expect("can build runtime");
+    let timed = async { tokio::time::sleep(Duration::from_millis(20)).await }
+        .into_instrumented_future()
+        .with_timer(&from_poll)
+        .with_timer_labeled(&LABELED, Kind::Sleep);
+    let timed_from_construction = async {}
+        .into_instrumented_future()
+        .with_timer(&from_construction)
+        .time_from_construction();
+    let never_polled = async {}.into_instrumented_future().with_timer(&from_poll);
+
+    std::thread::sleep(Duration::from_millis(20));
+    rt.block_on(async { tokio::join!(timed, timed_from_construction) });
+    drop(never_polled);
+
+    assert_eq!(from_poll.get_sample_count(), 1);
+    assert!(from_poll.get_sample_sum() >= 0.02);
+    assert_eq!(LABELED.get_sample_count(&Kind::Sleep), 1);
+    assert_eq!(from_construction.get_sample_count(), 1);
+    assert!(from_construction.get_sample_sum() >= 0.02);
+}
+
+#[test]
+fn poll_metrics_describe_time_spent_polling() {
+    use prometheus::{histogram_opts, opts};
+    use std::time::Duration;
+
+    let histogram = |name| Histogram::with_opts(histogram_opts!(name, "help")).unwrap();
+    let (busy, polls, longest) = (histogram("busy"), histogram("polls"), histogram("longest"));
+    let slow = IntCounter::with_opts(opts!("slow_polls", "help")).unwrap();
+
+    let blocking = async {
+        // Block the executor for a while, then yield so that the future is polled twice.
+        std::thread::sleep(Duration::from_millis(20));
+        let () = tokio::task::yield_now().await;
+    }
+    .into_instrumented_future()
+    .with_busy_time(&busy)
+    .with_poll_count(&polls)
+    .with_longest_poll(&longest)
+    .with_slow_poll_count(Duration::from_millis(10), &slow);
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("can build runtime");
+    rt.block_on(blocking);
+
+    assert!(busy.get_sample_sum() >= 0.02);
+    assert_eq!(polls.get_sample_sum(), 2.0);
+    assert!(longest.get_sample_sum() >= 0.02);
+    assert!(longest.get_sample_sum() <= busy.get_sample_sum());
+    assert_eq!(slow.get(), 1);
+}
+
+#[test]
+fn cancellations_are_counted() {
+    use crate::IntCounterWithLabels;
+    use lazy_static::lazy_static;
+    use prometheus::{opts, IntCounter};
+    use std::sync::Mutex;
+
+    crate::label_enum! {
+        #[label = "kind"]
+        enum Kind {
+            Request,
+        }
+    }
+
+    let cancelled = IntCounter::with_opts(opts!("cancelled", "help")).unwrap();
+    lazy_static! {
+        static ref LABELED: IntCounterWithLabels<Kind> =
+            IntCounterWithLabels::new("cancelled_labeled", "help").unwrap();
+    }
+    let outcomes = Arc::new(Mutex::new(vec![]));
+
+    struct RecordOutcome(Completion, Arc<Mutex<Vec<Outcome>>>);
+    impl Drop for RecordOutcome {
+        fn drop(&mut self) {
+            self.1.lock().unwrap().push(self.0.outcome());
+        }
+    }
+
+    let instrument = |fut: std::future::Ready<()>| {
+        let outcomes = Arc::clone(&outcomes);
+        fut.into_instrumented_future()
+            .with_cancel_count(&cancelled)
+            .with_cancel_count_labeled(&LABELED, Kind::Request)
+            .with_completion_guard(move |completion| {
+                Some(Box::new(RecordOutcome(completion, outcomes)))
+            })
+    };
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("can build runtime");
+    rt.block_on(instrument(std::future::ready(())));
+    assert_eq!(cancelled.get(), 0);
+
+    // A future that is never polled is cancelled, but its completion guard is never created.
+    drop(instrument(std::future::ready(())));
+    assert_eq!(cancelled.get(), 1);
+    assert_eq!(LABELED.handle(&Kind::Request).get(), 1);
+
+    let pending = std::future::pending::<()>()
+        .into_instrumented_future()
+        .with_cancel_count(&cancelled)
+        .with_completion_guard({
+            let outcomes = Arc::clone(&outcomes);
+            move |completion| Some(Box::new(RecordOutcome(completion, outcomes)))
+        });
+    rt.block_on(async {
+        tokio::select! {
+            _ = pending => unreachable!(),
+            _ = tokio::task::yield_now() => {}
+        }
+    });
+    assert_eq!(cancelled.get(), 2);
+    assert_eq!(
+        *outcomes.lock().unwrap(),
+        [Outcome::Success, Outcome::Cancelled]
+    );
+}
+
+#[test]
+fn results_are_counted_by_label() {
+    use crate::IntCounterWithLabels;
+    use lazy_static::lazy_static;
+
+    crate::label_enum! {
+        #[label = "result"]
+        enum ResultLabel {
+            Ok,
+            Err,
+        }
+    }
+
+    crate::label_enum! {
+        #[label = "error"]
+        enum ErrorKind {
+            Parse,
+            Io,
+        }
+    }
+
+    #[derive(Debug)]
+    enum Error {
+        Parse,
+        Io,
+    }
+
+    impl ErrorLabel for Error {
+        type Label = ErrorKind;
+
+        fn error_label(&self) -> ErrorKind {
+            match self {
+                Error::Parse => ErrorKind::Parse,
+                Error::Io => ErrorKind::Io,
+            }
+        }
+    }
+
+    lazy_static! {
+        static ref RESULTS: IntCounterWithLabels<ResultLabel> =
+            IntCounterWithLabels::new("results", "help").unwrap();
+        static ref ERRORS: IntCounterWithLabels<ErrorKind> =
+            IntCounterWithLabels::new("errors", "help").unwrap();
+    }
+
+    let instrument = |result: Result<(), Error>| {
+        std::future::ready(result)
+            .into_instrumented_future()
+            .with_result_count(&RESULTS, |result| match result {
+                Ok(_) => ResultLabel::Ok,
+                Err(_) => ResultLabel::Err,
+            })
+            .with_error_count(&ERRORS)
+    };
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("can build runtime");
+    rt.block_on(instrument(Ok(()))).unwrap();
+    rt.block_on(instrument(Err(Error::Parse))).unwrap_err();
+    rt.block_on(instrument(Err(Error::Io))).unwrap_err();
+    drop(instrument(Err(Error::Io)));
+
+    assert_eq!(RESULTS.handle(&ResultLabel::Ok).get(), 1);
+    assert_eq!(RESULTS.handle(&ResultLabel::Err).get(), 2);
+    assert_eq!(ERRORS.handle(&ErrorKind::Parse).get(), 1);
+    assert_eq!(ERRORS.handle(&ErrorKind::Io).get(), 1);
+}
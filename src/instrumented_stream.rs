@@ -0,0 +1,386 @@
+//! Utilities for instrumenting streams.
+//!
+//! This module is only compiled with the `futures` feature.
+//
+//  `dyn_drop` is explicitly allowed in this module for the same reason as in
+//  `instrumented_future`: resource guards are held for their drop implementations.
+#![allow(dyn_drop)]
+
+use super::{GenericCounterWithLabels, GuardedGauge, HistogramWithLabels, Labels};
+use futures_core::stream::{FusedStream, Stream};
+use pin_project::pin_project;
+use prometheus::{
+    core::{Atomic, GenericCounter},
+    Histogram,
+};
+use std::{any::Any, ops::Deref, pin::Pin, sync::Arc, task, time::Instant};
+
+/// A closure run before the first poll of an [`InstrumentedStream`], optionally returning a
+/// resource guard to hold until the stream ends.
+type PrePoll = Box<dyn FnOnce() -> Option<Box<dyn Any + Send>> + Send>;
+
+/// A closure run for every item yielded by an [`InstrumentedStream`].
+type OnItem = Box<dyn FnMut() + Send>;
+
+/// A closure observing the time before an item was yielded by an [`InstrumentedStream`], in
+/// seconds.
+type ItemGapTimer = Box<dyn Fn(f64) + Send>;
+
+/// A resource guard that observes how long a stream was alive once it ends or is dropped.
+struct LifetimeTimer {
+    /// Observes the lifetime, in seconds. This is only taken on drop.
+    observe: Option<Box<dyn FnOnce(f64) + Send>>,
+    started: Instant,
+}
+
+impl Drop for LifetimeTimer {
+    fn drop(&mut self) {
+        if let Some(observe) = self.observe.take() {
+            observe(self.started.elapsed().as_secs_f64());
+        }
+    }
+}
+
+/// An instrumented [`Stream`].
+///
+/// `InstrumentedStream` is the counterpart of [`InstrumentedFuture`][crate::InstrumentedFuture]
+/// for streams. It is created from an existing stream using
+/// [`IntoInstrumentedStream::into_instrumented_stream`], and can count the items the stream
+/// yields, track the stream as in flight with a [`GuardedGauge`], and time the gaps between items
+/// and the lifetime of the stream.
+///
+/// A stream is considered to have ended once its [`Stream::poll_next`] implementation returns
+/// `Poll::Ready(None)`. Guards and timers that last until the stream ends are also released if
+/// the stream is dropped before then.
+///
+/// # Examples
+///
+/// ```
+/// use futures_util::stream::{self, StreamExt};
+/// use lazy_static::lazy_static;
+/// use prometheus::{register_int_counter, register_int_gauge, IntCounter, IntGauge};
+/// use prometheus_utils::IntoInstrumentedStream;
+///
+/// lazy_static! {
+///     static ref ROWS: IntCounter = register_int_counter!("rows", "rows streamed").unwrap();
+///     static ref QUERIES: IntGauge =
+///         register_int_gauge!("queries_in_flight", "queries being streamed").unwrap();
+/// }
+///
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// let rows: Vec<_> = stream::iter(vec!["a", "b", "c"])
+///     .into_instrumented_stream()
+///     .with_item_count(&ROWS)
+///     .with_count_gauge(&QUERIES)
+///     .collect()
+///     .await;
+/// assert_eq!(ROWS.get(), 3);
+/// assert_eq!(QUERIES.get(), 0);
+/// # });
+/// ```
+#[pin_project]
+#[must_use = "streams do nothing unless polled"]
+pub struct InstrumentedStream<S: Stream> {
+    /// The inner [`Stream`]. Pinning is structural for `inner`, as for the inner future of an
+    /// `InstrumentedFuture`.
+    #[pin]
+    inner: S,
+    /// Closures to call before polling the inner `Stream` for the first time. These may, but are
+    /// not required to, return items to be `Drop`ped when the inner `Stream` ends or is dropped.
+    pre_polls: Vec<PrePoll>,
+    /// RAII guards that will be dropped once the stream has ended.
+    #[allow(dyn_drop)]
+    resource_guards: Vec<Box<dyn Any + Send>>,
+    /// Closures to call for every item yielded by the inner `Stream`.
+    on_item: Vec<OnItem>,
+    /// Closures observing the time between items, in seconds.
+    item_gap_timers: Vec<ItemGapTimer>,
+    /// When the previous item was yielded, or when the stream was first polled if it has not
+    /// yielded any items yet.
+    last_item: Option<Instant>,
+}
+
+/// Convert a [`Stream`] into an instrumented stream.
+///
+/// See the [`InstrumentedStream`] documentation for more information.
+pub trait IntoInstrumentedStream {
+    /// The underlying stream to be instrumented.
+    type Stream: Stream;
+    /// Convert this stream into an [`InstrumentedStream`].
+    fn into_instrumented_stream(self) -> InstrumentedStream<Self::Stream>;
+}
+
+impl<S: Stream> IntoInstrumentedStream for S {
+    type Stream = Self;
+    fn into_instrumented_stream(self) -> InstrumentedStream<Self> {
+        InstrumentedStream {
+            inner: self,
+            pre_polls: vec![],
+            resource_guards: vec![],
+            on_item: vec![],
+            item_gap_timers: vec![],
+            last_item: None,
+        }
+    }
+}
+
+impl<S: Stream> InstrumentedStream<S> {
+    /// Queue `guard_fn` to execute when the stream is first polled, retaining the returned value
+    /// until the stream ends or is dropped.
+    pub fn with_guard<GuardFn: FnOnce() -> Option<Box<dyn Any + Send>> + Send + 'static>(
+        mut self,
+        guard_fn: GuardFn,
+    ) -> Self {
+        self.pre_polls.push(Box::new(guard_fn));
+        self
+    }
+
+    /// Increment a Prometheus counter for every item yielded by this stream.
+    pub fn with_item_count<P: Atomic + 'static>(mut self, counter: &GenericCounter<P>) -> Self {
+        let counter = counter.clone();
+        self.on_item.push(Box::new(move || counter.inc()));
+        self
+    }
+
+    /// Increment a labeled Prometheus counter, using the provided `labels`, for every item
+    /// yielded by this stream.
+    ///
+    /// The series for `labels` is looked up for every item, as for any other update of the
+    /// counter. See [`InstrumentedStream::with_item_count`].
+    pub fn with_item_count_labeled<C, P, L>(mut self, counter: &'static C, labels: L) -> Self
+    where
+        C: Deref<Target = GenericCounterWithLabels<P, L>> + Sync,
+        P: Atomic + 'static,
+        L: Labels + Send + 'static,
+    {
+        self.on_item.push(Box::new(move || counter.inc(&labels)));
+        self
+    }
+
+    /// Increment a Prometheus gauge from when this stream is first polled until it ends or is
+    /// dropped.
+    ///
+    /// The gauge is incremented using the [`GuardedGauge::guarded_inc`] trait method, so it
+    /// counts the streams that are in flight.
+    pub fn with_count_gauge<G, T, P>(mut self, gauge: &'static G) -> Self
+    where
+        G: Deref<Target = T> + Sync,
+        T: GuardedGauge<P> + 'static,
+        P: Atomic + 'static,
+    {
+        self.pre_polls.push(Box::new(move || {
+            Some(Box::new(gauge.deref().guarded_inc()))
+        }));
+        self
+    }
+
+    /// Increment a shared Prometheus gauge from when this stream is first polled until it ends
+    /// or is dropped.
+    ///
+    /// This behaves like [`InstrumentedStream::with_count_gauge`], using the
+    /// [`GuardedGauge::guarded_inc_owned`] trait method, for gauges that are not stored in a
+    /// `static`.
+    pub fn with_count_gauge_owned<T, P>(mut self, gauge: Arc<T>) -> Self
+    where
+        T: GuardedGauge<P> + Send + Sync + 'static,
+        P: Atomic + 'static,
+    {
+        self.pre_polls
+            .push(Box::new(move || Some(Box::new(gauge.guarded_inc_owned()))));
+        self
+    }
+
+    /// Observe the time between the items yielded by this stream in a Prometheus histogram, in
+    /// seconds.
+    ///
+    /// The gap before the first item is measured from when the stream was first polled.
+    pub fn with_item_gap_timer(mut self, histogram: &Histogram) -> Self {
+        let histogram = histogram.clone();
+        self.item_gap_timers
+            .push(Box::new(move |gap| histogram.observe(gap)));
+        self
+    }
+
+    /// Observe the time between the items yielded by this stream in a labeled Prometheus
+    /// histogram, in seconds, using the provided `labels`.
+    ///
+    /// The series for `labels` is looked up for every item, as for any other update of the
+    /// histogram. See [`InstrumentedStream::with_item_gap_timer`].
+    pub fn with_item_gap_timer_labeled<H, L>(mut self, histogram: &'static H, labels: L) -> Self
+    where
+        H: Deref<Target = HistogramWithLabels<L>> + Sync,
+        L: Labels + Send + 'static,
+    {
+        self.item_gap_timers
+            .push(Box::new(move |gap| histogram.observe(&labels, gap)));
+        self
+    }
+
+    /// Observe how long this stream was alive in a Prometheus histogram, in seconds.
+    ///
+    /// The lifetime is measured from when the stream is first polled until it ends, or until it
+    /// is dropped if that happens first. Nothing is observed for streams that are never polled.
+    pub fn with_lifetime_timer(self, histogram: &Histogram) -> Self {
+        let histogram = histogram.clone();
+        self.with_lifetime_observer(Box::new(move |lifetime| histogram.observe(lifetime)))
+    }
+
+    /// Observe how long this stream was alive in a labeled Prometheus histogram, in seconds,
+    /// using the provided `labels`.
+    ///
+    /// The series for `labels` is looked up when the lifetime is observed, as for any other
+    /// update of the histogram. See [`InstrumentedStream::with_lifetime_timer`].
+    pub fn with_lifetime_timer_labeled<H, L>(self, histogram: &'static H, labels: L) -> Self
+    where
+        H: Deref<Target = HistogramWithLabels<L>> + Sync,
+        L: Labels + Send + 'static,
+    {
+        self.with_lifetime_observer(Box::new(move |lifetime| {
+            histogram.observe(&labels, lifetime)
+        }))
+    }
+
+    /// Call `observe` with how long this stream was alive, in seconds, once it ends or is
+    /// dropped.
+    fn with_lifetime_observer(mut self, observe: Box<dyn FnOnce(f64) + Send>) -> Self {
+        self.pre_polls.push(Box::new(move || {
+            Some(Box::new(LifetimeTimer {
+                observe: Some(observe),
+                started: Instant::now(),
+            }))
+        }));
+        self
+    }
+}
+
+impl<S: Stream> Stream for InstrumentedStream<S> {
+    /// An instrumented stream yields the same items as its inner stream.
+    type Item = S::Item;
+
+    /// Polls the inner stream.
+    ///
+    /// Items yielded by the inner stream are counted and timed. Once the inner stream ends,
+    /// resource guards such as gauge guards are dropped.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Option<S::Item>> {
+        use task::Poll::{Pending, Ready};
+        let pin_projection = self.project();
+        let last_item = pin_projection.last_item.get_or_insert_with(Instant::now);
+        for pre_poll in pin_projection.pre_polls.drain(..) {
+            if let Some(droppable) = pre_poll() {
+                pin_projection.resource_guards.push(droppable);
+            }
+        }
+        match pin_projection.inner.poll_next(cx) {
+            Pending => Pending,
+            Ready(Some(item)) => {
+                for on_item in pin_projection.on_item.iter_mut() {
+                    on_item();
+                }
+                let now = Instant::now();
+                let gap = now.saturating_duration_since(*last_item).as_secs_f64();
+                for timer in pin_projection.item_gap_timers.iter() {
+                    timer(gap);
+                }
+                *last_item = now;
+                Ready(Some(item))
+            }
+            // The inner stream ended, so drop any resource guards attached to this stream.
+            Ready(None) => {
+                pin_projection.resource_guards.clear();
+                Ready(None)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: FusedStream> FusedStream for InstrumentedStream<S> {
+    fn is_terminated(&self) -> bool {
+        self.inner.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IntoInstrumentedStream;
+    use crate::{HistogramWithLabels, IntCounterWithLabels};
+    use futures_util::stream::{self, StreamExt};
+    use lazy_static::lazy_static;
+    use prometheus::{histogram_opts, Histogram, IntGauge};
+    use std::sync::Arc;
+
+    crate::label_enum! {
+        #[label = "source"]
+        enum Source {
+            Database,
+        }
+    }
+
+    #[test]
+    fn items_are_counted_and_timed() {
+        lazy_static! {
+            static ref ITEMS: IntCounterWithLabels<Source> =
+                IntCounterWithLabels::new("stream_items", "help").unwrap();
+            static ref GAPS: HistogramWithLabels<Source> =
+                HistogramWithLabels::new("stream_gaps", "help").unwrap();
+        }
+        let lifetime = Histogram::with_opts(histogram_opts!("stream_lifetime", "help")).unwrap();
+        let gauge = Arc::new(IntGauge::new("streams_in_flight", "help").unwrap());
+
+        let mut stream = stream::iter(vec![1, 2, 3])
+            .into_instrumented_stream()
+            .with_item_count_labeled(&ITEMS, Source::Database)
+            .with_item_gap_timer_labeled(&GAPS, Source::Database)
+            .with_lifetime_timer(&lifetime)
+            .with_count_gauge_owned(Arc::clone(&gauge));
+        assert_eq!(gauge.get(), 0);
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("can build runtime");
+        assert_eq!(rt.block_on(stream.next()), Some(1));
+        assert_eq!(ITEMS.handle(&Source::Database).get(), 1);
+        assert_eq!(gauge.get(), 1);
+        assert_eq!(lifetime.get_sample_count(), 0);
+
+        let rest: Vec<_> = rt.block_on((&mut stream).collect());
+        assert_eq!(rest, [2, 3]);
+        assert_eq!(ITEMS.handle(&Source::Database).get(), 3);
+        assert_eq!(GAPS.handle(&Source::Database).get_sample_count(), 3);
+        assert_eq!(gauge.get(), 0);
+        assert_eq!(lifetime.get_sample_count(), 1);
+
+        drop(stream);
+        assert_eq!(lifetime.get_sample_count(), 1);
+    }
+
+    #[test]
+    fn guards_are_released_when_streams_are_dropped() {
+        let lifetime = Histogram::with_opts(histogram_opts!("dropped_lifetime", "help")).unwrap();
+        let gauge = Arc::new(IntGauge::new("dropped_in_flight", "help").unwrap());
+
+        let unpolled = stream::iter(vec![1])
+            .into_instrumented_stream()
+            .with_lifetime_timer(&lifetime)
+            .with_count_gauge_owned(Arc::clone(&gauge));
+        drop(unpolled);
+        assert_eq!(lifetime.get_sample_count(), 0);
+
+        let mut stream = stream::iter(vec![1, 2])
+            .into_instrumented_stream()
+            .with_lifetime_timer(&lifetime)
+            .with_count_gauge_owned(Arc::clone(&gauge));
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("can build runtime");
+        assert_eq!(rt.block_on(stream.next()), Some(1));
+        assert_eq!(gauge.get(), 1);
+
+        drop(stream);
+        assert_eq!(gauge.get(), 0);
+        assert_eq!(lifetime.get_sample_count(), 1);
+    }
+}
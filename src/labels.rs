@@ -1,9 +1,22 @@
-use crate::guards::DeferredAddWithLabels;
+use crate::{
+    builder::LabeledMetricBuilder,
+    cardinality::CardinalityLimiter,
+    error::Result,
+    expiry::IdleExpiry,
+    guards::{
+        DeferredAddWithLabels, DeferredAddWithOutcome, DeferredObserveWithLabels,
+        GenericGaugeGuardWithLabels, GenericGaugeSetGuardWithLabels, Outcome,
+    },
+};
 use prometheus::{
-    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, HistogramTimer,
-    HistogramVec, IntCounterVec, IntGaugeVec,
+    core::{
+        Atomic, AtomicF64, AtomicI64, AtomicU64, Collector, Desc, GenericCounter,
+        GenericCounterVec, GenericGauge, GenericGaugeVec, MetricVec, MetricVecBuilder, Number,
+    },
+    proto::MetricFamily,
+    Histogram, HistogramOpts, HistogramTimer, HistogramVec, IntCounter, Opts, Registry,
 };
-use std::marker::PhantomData;
+use std::{marker::PhantomData, sync::Arc, time::Duration};
 
 /// A sequence of values for Prometheus labels
 pub type LabelValues<'a> = Vec<&'a str>;
@@ -31,7 +44,11 @@ pub type LabelValues<'a> = Vec<&'a str>;
 /// You can define labeled metrics using types like [`IntCounterWithLabels`], which are
 /// parameterized by a type that implements `Labels`.
 ///
-/// [`IntCounterWithLabels`]: struct.IntCounterWithLabels.html
+/// Tuples of up to six `Labels` types also implement `Labels`, combining the labels of each
+/// element in order, so that existing label types can be combined without a wrapper struct.
+/// The unit type `()` implements `Labels` with no labels at all.
+///
+/// [`IntCounterWithLabels`]: type.IntCounterWithLabels.html
 pub trait Labels {
     /// The names of the labels that will be defined for the corresponding metric.
     fn label_names() -> Vec<&'static str>;
@@ -52,7 +69,577 @@ pub trait Labels {
     ///
     /// The sequence of values should correspond to the names provided in `label_names`,
     /// in order.
-    fn label_values(&self) -> LabelValues;
+    fn label_values(&self) -> LabelValues<'_>;
+
+    /// Call `f` with the label values to provide when emitting an event to Prometheus.
+    ///
+    /// This is how the labeled metric types read label values. The default implementation
+    /// passes along the result of `label_values`, which allocates a new vector each time.
+    /// Implementations can avoid that allocation by overriding this method to pass a slice
+    /// that lives on the stack, as the implementations generated by [`label_enum!`] and
+    /// `#[derive(Labels)]` do.
+    ///
+    /// The values passed to `f` must be the same as those returned by `label_values`.
+    ///
+    /// [`label_enum!`]: macro.label_enum.html
+    fn visit_label_values<'a, R>(&'a self, f: impl FnOnce(&[&'a str]) -> R) -> R {
+        f(&self.label_values())
+    }
+}
+
+/// A [`Labels`] type with a finite, known set of label values.
+///
+/// Every value of a `FiniteLabels` type corresponds to exactly one entry of
+/// [`Labels::possible_label_values`], which makes it possible to resolve the metric for each
+/// label set ahead of time. See [`HandleTable`] for how this is used.
+pub trait FiniteLabels: Labels {
+    /// The number of distinct label sets, i.e. the length of `possible_label_values()`.
+    const CARDINALITY: usize;
+
+    /// The position of this value's label values within `possible_label_values()`.
+    fn label_index(&self) -> usize;
+}
+
+/// A table of metric handles, one for each value of a [`FiniteLabels`] type.
+///
+/// Updating a labeled metric normally requires building its label values, hashing them, and
+/// looking up the corresponding metric under a lock. A `HandleTable` performs those lookups once,
+/// up front, so that [`HandleTable::get`] is only an array index.
+///
+/// Created by calling `handles` on a labeled metric, such as
+/// [`GenericCounterWithLabels::handles`].
+pub struct HandleTable<L: FiniteLabels, M> {
+    handles: Vec<M>,
+    _labels: PhantomData<L>,
+}
+
+impl<L: FiniteLabels, M> HandleTable<L, M> {
+    fn new(handle: impl Fn(&[&str]) -> M) -> Self {
+        let handles: Vec<M> = L::possible_label_values()
+            .iter()
+            .map(|vals| handle(vals))
+            .collect();
+        debug_assert_eq!(handles.len(), L::CARDINALITY);
+
+        Self {
+            handles,
+            _labels: PhantomData,
+        }
+    }
+
+    /// The metric handle for the provided `labels`.
+    pub fn get(&self, labels: &L) -> &M {
+        &self.handles[labels.label_index()]
+    }
+
+    /// The metric handle for the labels with the provided [`FiniteLabels::label_index`].
+    pub(crate) fn at(&self, index: usize) -> &M {
+        &self.handles[index]
+    }
+}
+
+impl<L: FiniteLabels, M: Clone> Clone for HandleTable<L, M> {
+    fn clone(&self) -> Self {
+        Self {
+            handles: self.handles.clone(),
+            _labels: PhantomData,
+        }
+    }
+}
+
+/// A handle to the series of a labeled metric for one label set.
+///
+/// Created by calling `handle` on a labeled metric, such as [`GenericCounterWithLabels::handle`],
+/// or held in a [`HandleTable`]. Clones of a handle update the same series.
+///
+/// If a cardinality limit redirected the label set to the overflow series, every update made
+/// through the handle is counted by the limit's overflow counter, as it would be for an update
+/// made through the labeled metric.
+#[derive(Clone)]
+pub struct SeriesHandle<M> {
+    metric: M,
+    /// The overflow counter to increment for each update, if the handle is for the overflow
+    /// series.
+    overflow: Option<IntCounter>,
+}
+
+impl<M> SeriesHandle<M> {
+    /// Count an update as redirected, if the handle is for the overflow series.
+    fn record_update(&self) {
+        if let Some(overflow) = &self.overflow {
+            overflow.inc();
+        }
+    }
+}
+
+impl<P: Atomic> SeriesHandle<GenericCounter<P>> {
+    /// Increment the counter by `1`.
+    pub fn inc(&self) {
+        self.record_update();
+        self.metric.inc();
+    }
+
+    /// Increment the counter by `v`.
+    pub fn inc_by(&self, v: P::T) {
+        self.record_update();
+        self.metric.inc_by(v);
+    }
+
+    /// Return the counter value.
+    pub fn get(&self) -> P::T {
+        self.metric.get()
+    }
+}
+
+impl<P: Atomic> SeriesHandle<GenericGauge<P>> {
+    /// Set the gauge to `v`.
+    pub fn set(&self, v: P::T) {
+        self.record_update();
+        self.metric.set(v);
+    }
+
+    /// Increase the gauge by `1`.
+    pub fn inc(&self) {
+        self.record_update();
+        self.metric.inc();
+    }
+
+    /// Decrease the gauge by `1`.
+    pub fn dec(&self) {
+        self.record_update();
+        self.metric.dec();
+    }
+
+    /// Increase the gauge by `v`.
+    pub fn add(&self, v: P::T) {
+        self.record_update();
+        self.metric.add(v);
+    }
+
+    /// Decrease the gauge by `v`.
+    pub fn sub(&self, v: P::T) {
+        self.record_update();
+        self.metric.sub(v);
+    }
+
+    /// Return the gauge value.
+    pub fn get(&self) -> P::T {
+        self.metric.get()
+    }
+}
+
+impl SeriesHandle<Histogram> {
+    /// Add a single observation to the histogram.
+    pub fn observe(&self, v: f64) {
+        self.record_update();
+        self.metric.observe(v);
+    }
+
+    /// Return a timer that observes the elapsed time in seconds when it is dropped or stopped.
+    pub fn start_timer(&self) -> HistogramTimer {
+        self.record_update();
+        self.metric.start_timer()
+    }
+
+    /// Observe the execution time of `f`, in seconds.
+    pub fn observe_closure_duration<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        self.record_update();
+        self.metric.observe_closure_duration(f)
+    }
+
+    /// Return the sum of all samples.
+    pub fn get_sample_sum(&self) -> f64 {
+        self.metric.get_sample_sum()
+    }
+
+    /// Return the count of all samples.
+    pub fn get_sample_count(&self) -> u64 {
+        self.metric.get_sample_count()
+    }
+}
+
+/// A value that can be used for a single Prometheus label.
+///
+/// This is the building block used by `#[derive(Labels)]`: each field of a derived label struct
+/// must implement `LabelValue`. Enums declared with [`label_enum!`] implement it automatically.
+///
+/// [`label_enum!`]: macro.label_enum.html
+pub trait LabelValue {
+    /// The label value to provide when emitting an event to Prometheus.
+    fn as_label_value(&self) -> &str;
+
+    /// All of the values this label can take on.
+    ///
+    /// This is empty for types that have no finite set of values, like strings. See
+    /// [`Labels::possible_label_values`] for how these values are used.
+    fn possible_values() -> Vec<&'static str> {
+        Vec::new()
+    }
+}
+
+impl LabelValue for &str {
+    fn as_label_value(&self) -> &str {
+        self
+    }
+}
+
+impl LabelValue for String {
+    fn as_label_value(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl LabelValue for bool {
+    fn as_label_value(&self) -> &str {
+        if *self {
+            "true"
+        } else {
+            "false"
+        }
+    }
+
+    fn possible_values() -> Vec<&'static str> {
+        vec!["false", "true"]
+    }
+}
+
+/// Compute every combination of the given per-label values, in order.
+///
+/// If any label has no possible values, there are no combinations.
+#[doc(hidden)]
+pub fn cartesian_product(values: Vec<Vec<&'static str>>) -> Vec<LabelValues<'static>> {
+    label_set_product(
+        values
+            .into_iter()
+            .map(|label| label.into_iter().map(|value| vec![value]).collect())
+            .collect(),
+    )
+}
+
+/// Compute every concatenation of one label set from each of the given groups, in order.
+///
+/// If any group has no label sets, there are no combinations.
+fn label_set_product(groups: Vec<Vec<LabelValues<'static>>>) -> Vec<LabelValues<'static>> {
+    groups
+        .into_iter()
+        .fold(vec![vec![]], |combinations, group| {
+            combinations
+                .iter()
+                .flat_map(|prefix| {
+                    group.iter().map(move |vals| {
+                        let mut combination = prefix.clone();
+                        combination.extend(vals);
+                        combination
+                    })
+                })
+                .collect()
+        })
+}
+
+/// A metric with no labels.
+///
+/// This makes it possible to use the labeled metric types, and APIs built on them, for metrics
+/// that do not need any labels.
+impl Labels for () {
+    fn label_names() -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn possible_label_values() -> Vec<LabelValues<'static>> {
+        vec![vec![]]
+    }
+
+    fn label_values(&self) -> LabelValues<'_> {
+        vec![]
+    }
+
+    fn visit_label_values<'a, R>(&'a self, f: impl FnOnce(&[&'a str]) -> R) -> R {
+        f(&[])
+    }
+}
+
+impl FiniteLabels for () {
+    const CARDINALITY: usize = 1;
+
+    fn label_index(&self) -> usize {
+        0
+    }
+}
+
+/// The maximum number of label values that a [`LabelBuffer`] stores without allocating.
+const INLINE_LABEL_VALUES: usize = 16;
+
+/// A buffer of label values, stored on the stack unless there are more than
+/// [`INLINE_LABEL_VALUES`] of them.
+struct LabelBuffer<'a> {
+    inline: [&'a str; INLINE_LABEL_VALUES],
+    len: usize,
+    /// Holds every value once the inline buffer is outgrown; empty (and unallocated) until then.
+    spilled: Vec<&'a str>,
+}
+
+impl<'a> LabelBuffer<'a> {
+    fn new() -> Self {
+        Self {
+            inline: [""; INLINE_LABEL_VALUES],
+            len: 0,
+            spilled: Vec::new(),
+        }
+    }
+
+    fn extend(&mut self, vals: &[&'a str]) {
+        if !self.spilled.is_empty() {
+            self.spilled.extend_from_slice(vals);
+        } else if self.len + vals.len() <= INLINE_LABEL_VALUES {
+            self.inline[self.len..self.len + vals.len()].copy_from_slice(vals);
+            self.len += vals.len();
+        } else {
+            self.spilled.extend_from_slice(&self.inline[..self.len]);
+            self.spilled.extend_from_slice(vals);
+        }
+    }
+
+    fn as_slice(&self) -> &[&'a str] {
+        if self.spilled.is_empty() {
+            &self.inline[..self.len]
+        } else {
+            &self.spilled
+        }
+    }
+}
+
+/// Implement `Labels` and `FiniteLabels` for a tuple of label types, whose labels are the labels
+/// of each element in order.
+macro_rules! impl_labels_for_tuple {
+    ($($T:ident . $i:tt),+) => {
+        impl<$($T: Labels),+> Labels for ($($T,)+) {
+            fn label_names() -> Vec<&'static str> {
+                let mut names = vec![];
+                $(names.extend($T::label_names());)+
+                names
+            }
+
+            fn possible_label_values() -> Vec<LabelValues<'static>> {
+                label_set_product(vec![$($T::possible_label_values()),+])
+            }
+
+            fn label_values(&self) -> LabelValues<'_> {
+                let mut values = vec![];
+                $(values.extend(self.$i.label_values());)+
+                values
+            }
+
+            fn visit_label_values<'a, R>(&'a self, f: impl FnOnce(&[&'a str]) -> R) -> R {
+                let mut values = LabelBuffer::new();
+                $(self.$i.visit_label_values(|vals| values.extend(vals));)+
+                f(values.as_slice())
+            }
+        }
+
+        // Label sets are ordered as in `label_set_product`, with the last element varying
+        // fastest.
+        impl<$($T: FiniteLabels),+> FiniteLabels for ($($T,)+) {
+            const CARDINALITY: usize = 1 $(* $T::CARDINALITY)+;
+
+            fn label_index(&self) -> usize {
+                let mut index = 0;
+                $(index = index * $T::CARDINALITY + self.$i.label_index();)+
+                index
+            }
+        }
+    };
+}
+
+impl_labels_for_tuple!(A.0);
+impl_labels_for_tuple!(A.0, B.1);
+impl_labels_for_tuple!(A.0, B.1, C.2);
+impl_labels_for_tuple!(A.0, B.1, C.2, D.3);
+impl_labels_for_tuple!(A.0, B.1, C.2, D.3, E.4);
+impl_labels_for_tuple!(A.0, B.1, C.2, D.3, E.4, F.5);
+
+/// The metric vector backing each of the labeled metric types, along with any limits placed on
+/// the label sets it may contain.
+///
+/// This is also the [`Collector`] registered on behalf of the labeled metric types, so that idle
+/// series can be removed at gather time.
+struct LabeledVec<V> {
+    vec: V,
+    limiter: Option<Arc<CardinalityLimiter>>,
+    expiry: Arc<IdleExpiry>,
+}
+
+impl<V: Clone> Clone for LabeledVec<V> {
+    fn clone(&self) -> Self {
+        Self {
+            vec: self.vec.clone(),
+            limiter: self.limiter.clone(),
+            expiry: self.expiry.clone(),
+        }
+    }
+}
+
+impl<B: MetricVecBuilder + 'static> LabeledVec<MetricVec<B>> {
+    fn new(vec: MetricVec<B>) -> Self {
+        Self {
+            vec,
+            limiter: None,
+            expiry: Arc::new(IdleExpiry::new()),
+        }
+    }
+
+    /// Return the metric for the provided label values in order to update it, admitting them
+    /// under any cardinality limit.
+    fn get(&self, vals: &[&str]) -> B::M {
+        let vals = match &self.limiter {
+            Some(limiter) => limiter.admit(vals),
+            None => vals,
+        };
+        self.expiry.touch(vals);
+        self.vec.with_label_values(vals)
+    }
+
+    /// Return the metric for the provided label values in order to initialize it, if they are
+    /// admitted under any cardinality limit. This is not an event, so nothing is counted if they
+    /// do not fit.
+    fn initialize(&self, vals: &[&str]) -> Option<B::M> {
+        match &self.limiter {
+            Some(limiter) if !limiter.try_admit(vals) => None,
+            _ => Some(self.vec.with_label_values(vals)),
+        }
+    }
+
+    /// Return the metric for the provided `labels`, subject to any cardinality limit.
+    fn get_for<L: Labels>(&self, labels: &L) -> B::M {
+        labels.visit_label_values(|vals| self.get(vals))
+    }
+
+    /// Return a handle to the metric for the provided label values, admitting them under any
+    /// cardinality limit like [`LabeledVec::get`].
+    fn handle(&self, vals: &[&str]) -> SeriesHandle<B::M> {
+        let (vals, overflow) = match &self.limiter {
+            Some(limiter) => limiter.admit_handle(vals),
+            None => (vals, None),
+        };
+        self.expiry.touch(vals);
+        SeriesHandle {
+            metric: self.vec.with_label_values(vals),
+            overflow,
+        }
+    }
+
+    /// Return a handle to the metric for the provided `labels`. See [`LabeledVec::handle`].
+    fn handle_for<L: Labels>(&self, labels: &L) -> SeriesHandle<B::M> {
+        labels.visit_label_values(|vals| self.handle(vals))
+    }
+
+    /// Return the metric for the provided `labels` in order to update it, like
+    /// [`LabeledVec::get_for`], and keep its label set from expiring until [`LabeledVec::unpin`]
+    /// is called with the returned key.
+    fn pin_for<L: Labels>(&self, labels: &L) -> (B::M, Option<u64>) {
+        labels.visit_label_values(|vals| {
+            let vals = match &self.limiter {
+                Some(limiter) => limiter.admit(vals),
+                None => vals,
+            };
+            let key = self.expiry.pin(vals);
+            (self.vec.with_label_values(vals), key)
+        })
+    }
+
+    /// Release a pin taken by [`LabeledVec::pin_for`].
+    fn unpin(&self, key: Option<u64>) {
+        self.expiry.unpin(key);
+    }
+
+    /// Return the metric for the provided label values, in order to read it.
+    ///
+    /// Unlike [`LabeledVec::get`], this does not admit the label values under a cardinality
+    /// limit, nor count as activity for expiry. Label sets that were never admitted are read
+    /// from the overflow series.
+    fn peek(&self, vals: &[&str]) -> B::M {
+        let vals = match &self.limiter {
+            Some(limiter) => limiter.resolve(vals),
+            None => vals,
+        };
+        self.vec.with_label_values(vals)
+    }
+
+    /// Return the metric for the provided `labels`, in order to read it. See [`LabeledVec::peek`].
+    fn peek_for<L: Labels>(&self, labels: &L) -> B::M {
+        labels.visit_label_values(|vals| self.peek(vals))
+    }
+
+    /// Remove the metric for the provided `labels`, returning whether it existed.
+    fn remove_for<L: Labels>(&self, labels: &L) -> bool {
+        labels.visit_label_values(|vals| self.remove(vals))
+    }
+
+    /// Remove the metric for the provided label values, returning whether it existed.
+    fn remove(&self, vals: &[&str]) -> bool {
+        if let Some(limiter) = &self.limiter {
+            limiter.forget(vals);
+        }
+        self.expiry.forget(vals);
+        self.vec.remove_label_values(vals).is_ok()
+    }
+
+    /// Remove the metrics for every label set.
+    fn reset<L: Labels>(&self) {
+        self.vec.reset();
+        if let Some(limiter) = &self.limiter {
+            limiter.clear();
+            Self::admit_possible_values::<L>(limiter);
+        }
+        self.expiry.clear();
+    }
+
+    /// Remove the metrics for every label set that has been idle for longer than the TTL,
+    /// returning the number of label sets removed.
+    fn sweep(&self) -> usize {
+        self.expiry.remove_expired(|vals| {
+            if let Some(limiter) = &self.limiter {
+                limiter.forget(vals);
+            }
+            let _ = self.vec.remove_label_values(vals);
+        })
+    }
+
+    fn register(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.clone()))?;
+        Ok(())
+    }
+
+    fn limit_cardinality<L: Labels>(&mut self, max_series: usize, overflow: IntCounter) {
+        let limiter = CardinalityLimiter::new(max_series, L::label_names().len(), overflow);
+        Self::admit_possible_values::<L>(&limiter);
+        self.limiter = Some(Arc::new(limiter));
+    }
+
+    /// Admit the possible values of `L` up front, as far as the limit allows. These are not
+    /// events, so label sets that do not fit are not counted as redirected.
+    fn admit_possible_values<L: Labels>(limiter: &CardinalityLimiter) {
+        for vals in L::possible_label_values() {
+            limiter.try_admit(&vals);
+        }
+    }
+
+    fn expire_after(&self, ttl: Duration) {
+        self.expiry.set_ttl(ttl);
+    }
+}
+
+impl<B: MetricVecBuilder + 'static> Collector for LabeledVec<MetricVec<B>> {
+    fn desc(&self) -> Vec<&Desc> {
+        self.vec.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.sweep();
+        self.vec.collect()
+    }
 }
 
 /// A Prometheus integer counter metric, with labels described by the type `L`.
@@ -61,36 +648,152 @@ pub trait Labels {
 /// for an overview of Prometheus metric labels.
 ///
 /// [`Labels`]: trait.Labels.html
-pub struct IntCounterWithLabels<L: Labels> {
-    metric: IntCounterVec,
+pub type IntCounterWithLabels<L> = GenericCounterWithLabels<AtomicU64, L>;
+
+/// A Prometheus floating point counter metric, with labels described by the type `L`.
+///
+/// The type `L` must implement the [`Labels`] trait; see the documentation for that trait
+/// for an overview of Prometheus metric labels.
+///
+/// [`Labels`]: trait.Labels.html
+pub type CounterWithLabels<L> = GenericCounterWithLabels<AtomicF64, L>;
+
+/// A Prometheus counter metric, with values of the atomic type `P` and labels described by the
+/// type `L`.
+///
+/// This is generally used through the [`IntCounterWithLabels`] and [`CounterWithLabels`]
+/// aliases.
+pub struct GenericCounterWithLabels<P: Atomic + 'static, L: Labels> {
+    metric: LabeledVec<GenericCounterVec<P>>,
     _labels: PhantomData<L>,
 }
 
-impl<L: Labels> IntCounterWithLabels<L> {
-    /// Construct and immediately register a new `IntCounterWithLabels` instance.
-    pub fn register_new(name: &str, help: &str) -> IntCounterWithLabels<L> {
-        let metric = register_int_counter_vec!(name, help, &L::label_names()).unwrap();
+impl<P: Atomic + 'static, L: Labels> GenericCounterWithLabels<P, L> {
+    /// Construct a new counter instance, without registering it.
+    pub fn new(name: &str, help: &str) -> Result<Self> {
+        Self::from_opts(Opts::new(name, help))
+    }
+
+    /// Return a builder for a counter, for setting options like a namespace or constant labels.
+    pub fn builder(name: &str, help: &str) -> LabeledMetricBuilder<Self> {
+        LabeledMetricBuilder::new(name, help)
+    }
+
+    pub(crate) fn from_opts(opts: Opts) -> Result<Self> {
+        let metric = GenericCounterVec::new(opts, &L::label_names())?;
 
         for vals in L::possible_label_values() {
-            metric.with_label_values(&vals).inc_by(0);
+            metric.with_label_values(&vals).inc_by(P::T::from_i64(0));
         }
 
-        Self {
-            metric,
+        Ok(Self {
+            metric: LabeledVec::new(metric),
             _labels: PhantomData,
+        })
+    }
+
+    /// Construct a new counter instance and register it in `registry`.
+    pub fn try_register_in(registry: &Registry, name: &str, help: &str) -> Result<Self> {
+        let counter = Self::new(name, help)?;
+        counter.register_in(registry)?;
+        Ok(counter)
+    }
+
+    /// Construct and immediately register a new counter instance.
+    ///
+    /// The metric is registered in the default registry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric cannot be constructed or registered. See
+    /// [`GenericCounterWithLabels::try_register_in`] for a fallible alternative.
+    pub fn register_new(name: &str, help: &str) -> Self {
+        Self::try_register_in(prometheus::default_registry(), name, help).unwrap()
+    }
+
+    /// Limit the number of distinct label sets this counter may contain.
+    ///
+    /// Once `max_series` distinct label sets have been used, events for any other label set are
+    /// recorded with every label set to [`OVERFLOW_LABEL_VALUE`] instead, and `overflow` is
+    /// incremented once for each redirected event. The label sets in
+    /// [`Labels::possible_label_values`] are admitted first, and count towards the limit.
+    ///
+    /// [`OVERFLOW_LABEL_VALUE`]: constant.OVERFLOW_LABEL_VALUE.html
+    pub fn with_cardinality_limit(mut self, max_series: usize, overflow: IntCounter) -> Self {
+        self.metric.limit_cardinality::<L>(max_series, overflow);
+        self
+    }
+
+    /// Remove label sets that have not been updated within `ttl`.
+    ///
+    /// Idle label sets are removed whenever the counter is gathered from a registry, or when
+    /// [`GenericCounterWithLabels::sweep`] is called. This applies whether the counter is
+    /// registered before or after this is called.
+    ///
+    /// Updates made through a handle from [`GenericCounterWithLabels::handle`] do not count as
+    /// activity, and a handle whose label set is removed is detached: updates made through it
+    /// afterwards are no longer exported.
+    pub fn with_idle_ttl(self, ttl: Duration) -> Self {
+        self.metric.expire_after(ttl);
+        self
+    }
+
+    /// Register this counter in `registry`.
+    pub fn register_in(&self, registry: &Registry) -> Result<()> {
+        self.metric.register(registry)
+    }
+
+    /// Remove the counter for the provided `labels`, returning whether it existed.
+    ///
+    /// Handles for the removed label set are no longer exported, even if they are updated.
+    pub fn remove(&self, labels: &L) -> bool {
+        self.metric.remove_for(labels)
+    }
+
+    /// Remove the counter for every label set.
+    ///
+    /// As when the counter is constructed, the label sets in [`Labels::possible_label_values`]
+    /// are then initialized to `0`.
+    pub fn reset(&self) {
+        self.metric.reset::<L>();
+        for vals in L::possible_label_values() {
+            if let Some(counter) = self.metric.initialize(&vals) {
+                counter.inc_by(P::T::from_i64(0));
+            }
         }
     }
 
+    /// Remove label sets that have been idle for longer than the TTL set by
+    /// [`GenericCounterWithLabels::with_idle_ttl`], returning the number of label sets removed.
+    pub fn sweep(&self) -> usize {
+        self.metric.sweep()
+    }
+
     /// Increment the metric by `1`, using the provided `labels` for the event.
     pub fn inc(&self, labels: &L) {
-        self.metric.with_label_values(&labels.label_values()).inc();
+        self.metric.get_for(labels).inc();
     }
 
     /// Increment the metric by `v`, using the provided `labels` for the event.
-    pub fn add(&self, v: u64, labels: &L) {
-        self.metric
-            .with_label_values(&labels.label_values())
-            .inc_by(v);
+    pub fn add(&self, v: P::T, labels: &L) {
+        self.metric.get_for(labels).inc_by(v);
+    }
+
+    /// Return a handle to the counter for the provided `labels`.
+    ///
+    /// The handle is bound to one label set, so updating it skips the label lookup performed by
+    /// [`GenericCounterWithLabels::inc`] and [`GenericCounterWithLabels::add`].
+    ///
+    /// Getting a handle admits `labels` under any cardinality limit, as an update would. If they
+    /// do not fit, the handle is for the overflow series, and each update made through it is
+    /// counted as redirected.
+    ///
+    /// If the label set is later removed, by [`GenericCounterWithLabels::remove`], by
+    /// [`GenericCounterWithLabels::reset`] or by expiring after
+    /// [`GenericCounterWithLabels::with_idle_ttl`], the handle is detached: updates made through it
+    /// are no longer exported.
+    pub fn handle(&self, labels: &L) -> SeriesHandle<GenericCounter<P>> {
+        self.metric.handle_for(labels)
     }
 
     /// Creates a guard value that will increment the metric by `1`, using the provided `labels`,
@@ -98,8 +801,8 @@ impl<L: Labels> IntCounterWithLabels<L> {
     ///
     /// Prior to dropping, the labels can be altered using [`DeferredAddWithLabels::with_labels`].
     #[must_use]
-    pub fn deferred_inc<'a>(&'a self, labels: L) -> DeferredAddWithLabels<'a, L> {
-        DeferredAddWithLabels::new(self, 1, labels)
+    pub fn deferred_inc<'a>(&'a self, labels: L) -> DeferredAddWithLabels<'a, L, P> {
+        DeferredAddWithLabels::new(self, P::T::from_i64(1), labels)
     }
 
     /// Creates a guard value that will increment the metric by `v`, using the provided `labels`,
@@ -107,67 +810,267 @@ impl<L: Labels> IntCounterWithLabels<L> {
     ///
     /// Prior to dropping, the labels can be altered using [`DeferredAddWithLabels::with_labels`].
     #[must_use]
-    pub fn deferred_add<'a>(&'a self, v: u64, labels: L) -> DeferredAddWithLabels<'a, L> {
+    pub fn deferred_add<'a>(&'a self, v: P::T, labels: L) -> DeferredAddWithLabels<'a, L, P> {
         DeferredAddWithLabels::new(self, v, labels)
     }
 }
 
+impl<P: Atomic + 'static, L: Labels> GenericCounterWithLabels<P, (L, Outcome)> {
+    /// Creates a guard value that will increment the metric by `1` once dropped, using the
+    /// provided `labels` along with the [`Outcome`] of the guarded operation.
+    ///
+    /// Call [`DeferredAddWithOutcome::complete`] once the operation succeeds. Returning early or
+    /// panicking before then records a [`Outcome::Cancelled`] or [`Outcome::Panicked`] outcome.
+    #[must_use]
+    pub fn deferred_inc_with_outcome<'a>(&'a self, labels: L) -> DeferredAddWithOutcome<'a, L, P> {
+        DeferredAddWithOutcome::new(self, P::T::from_i64(1), labels)
+    }
+
+    /// Creates a guard value that will increment the metric by `v` once dropped, using the
+    /// provided `labels` along with the [`Outcome`] of the guarded operation.
+    ///
+    /// Call [`DeferredAddWithOutcome::complete`] once the operation succeeds. Returning early or
+    /// panicking before then records a [`Outcome::Cancelled`] or [`Outcome::Panicked`] outcome.
+    #[must_use]
+    pub fn deferred_add_with_outcome<'a>(
+        &'a self,
+        v: P::T,
+        labels: L,
+    ) -> DeferredAddWithOutcome<'a, L, P> {
+        DeferredAddWithOutcome::new(self, v, labels)
+    }
+}
+
+impl<P: Atomic + 'static, L: FiniteLabels> GenericCounterWithLabels<P, L> {
+    /// Return a table of handles to the counter, one for each possible value of `L`.
+    ///
+    /// As with [`GenericCounterWithLabels::handle`], a handle is detached if its label set is
+    /// removed.
+    pub fn handles(&self) -> HandleTable<L, SeriesHandle<GenericCounter<P>>> {
+        HandleTable::new(|vals| self.metric.handle(vals))
+    }
+}
+
 /// A Prometheus integer gauge metric, with labels described by the type `L`.
 ///
 /// The type `L` must implement the [`Labels`] trait; see the documentation for that trait
 /// for an overview of Prometheus metric labels.
 ///
 /// [`Labels`]: trait.Labels.html
-pub struct IntGaugeWithLabels<L: Labels> {
-    metric: IntGaugeVec,
+pub type IntGaugeWithLabels<L> = GenericGaugeWithLabels<AtomicI64, L>;
+
+/// A Prometheus floating point gauge metric, with labels described by the type `L`.
+///
+/// The type `L` must implement the [`Labels`] trait; see the documentation for that trait
+/// for an overview of Prometheus metric labels.
+///
+/// [`Labels`]: trait.Labels.html
+pub type GaugeWithLabels<L> = GenericGaugeWithLabels<AtomicF64, L>;
+
+/// A Prometheus gauge metric, with values of the atomic type `P` and labels described by the
+/// type `L`.
+///
+/// This is generally used through the [`IntGaugeWithLabels`] and [`GaugeWithLabels`] aliases.
+pub struct GenericGaugeWithLabels<P: Atomic + 'static, L: Labels> {
+    metric: LabeledVec<GenericGaugeVec<P>>,
     _labels: PhantomData<L>,
 }
 
-impl<L: Labels> IntGaugeWithLabels<L> {
-    /// Construct and immediately register a new `IntGaugeWithLabels` instance.
-    pub fn register_new(name: &str, help: &str) -> IntGaugeWithLabels<L> {
-        let metric = register_int_gauge_vec!(name, help, &L::label_names()).unwrap();
+impl<P: Atomic + 'static, L: Labels> GenericGaugeWithLabels<P, L> {
+    /// Construct a new gauge instance, without registering it.
+    pub fn new(name: &str, help: &str) -> Result<Self> {
+        Self::from_opts(Opts::new(name, help))
+    }
+
+    /// Return a builder for a gauge, for setting options like a namespace or constant labels.
+    pub fn builder(name: &str, help: &str) -> LabeledMetricBuilder<Self> {
+        LabeledMetricBuilder::new(name, help)
+    }
+
+    pub(crate) fn from_opts(opts: Opts) -> Result<Self> {
+        let metric = GenericGaugeVec::new(opts, &L::label_names())?;
 
         // Note: for gauges, unlike counters, we don't need to -- and should not! -- prepopulate
         // the metric with the possible labels. Unlike counters, which are only updated when an
         // event occurs, gauges _always_ have a value. Moreover, we cannot make assumptions about
         // an initial gauge value (unlike an initial 0 value for counters).
 
-        Self {
-            metric,
+        Ok(Self {
+            metric: LabeledVec::new(metric),
             _labels: PhantomData,
-        }
+        })
+    }
+
+    /// Construct a new gauge instance and register it in `registry`.
+    pub fn try_register_in(registry: &Registry, name: &str, help: &str) -> Result<Self> {
+        let gauge = Self::new(name, help)?;
+        gauge.register_in(registry)?;
+        Ok(gauge)
+    }
+
+    /// Construct and immediately register a new gauge instance.
+    ///
+    /// The metric is registered in the default registry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric cannot be constructed or registered. See
+    /// [`GenericGaugeWithLabels::try_register_in`] for a fallible alternative.
+    pub fn register_new(name: &str, help: &str) -> Self {
+        Self::try_register_in(prometheus::default_registry(), name, help).unwrap()
+    }
+
+    /// Limit the number of distinct label sets this gauge may contain.
+    ///
+    /// Once `max_series` distinct label sets have been used, events for any other label set are
+    /// recorded with every label set to [`OVERFLOW_LABEL_VALUE`] instead, and `overflow` is
+    /// incremented once for each redirected event. The label sets in
+    /// [`Labels::possible_label_values`] are admitted first, and count towards the limit.
+    ///
+    /// [`OVERFLOW_LABEL_VALUE`]: constant.OVERFLOW_LABEL_VALUE.html
+    pub fn with_cardinality_limit(mut self, max_series: usize, overflow: IntCounter) -> Self {
+        self.metric.limit_cardinality::<L>(max_series, overflow);
+        self
+    }
+
+    /// Remove label sets that have not been updated within `ttl`.
+    ///
+    /// Idle label sets are removed whenever the gauge is gathered from a registry, or when
+    /// [`GenericGaugeWithLabels::sweep`] is called. This applies whether the gauge is registered
+    /// before or after this is called.
+    ///
+    /// Updates made through a handle from [`GenericGaugeWithLabels::handle`] do not count as
+    /// activity, and a handle whose label set is removed is detached: updates made through it
+    /// afterwards are no longer exported.
+    pub fn with_idle_ttl(self, ttl: Duration) -> Self {
+        self.metric.expire_after(ttl);
+        self
+    }
+
+    /// Register this gauge in `registry`.
+    pub fn register_in(&self, registry: &Registry) -> Result<()> {
+        self.metric.register(registry)
+    }
+
+    /// A collector for this gauge, to be registered in place of the gauge by metrics built on top
+    /// of it.
+    pub(crate) fn collector(&self) -> Box<dyn Collector> {
+        Box::new(self.metric.clone())
+    }
+
+    /// The fully-qualified name of this gauge.
+    #[cfg(feature = "leak-detection")]
+    pub(crate) fn name(&self) -> &str {
+        &self.metric.vec.desc()[0].fq_name
+    }
+
+    /// Remove the gauge for the provided `labels`, returning whether it existed.
+    ///
+    /// Handles for the removed label set are no longer exported, even if they are updated.
+    pub fn remove(&self, labels: &L) -> bool {
+        self.metric.remove_for(labels)
+    }
+
+    /// Remove the gauge for every label set.
+    pub fn reset(&self) {
+        self.metric.reset::<L>();
+    }
+
+    /// Remove label sets that have been idle for longer than the TTL set by
+    /// [`GenericGaugeWithLabels::with_idle_ttl`], returning the number of label sets removed.
+    pub fn sweep(&self) -> usize {
+        self.metric.sweep()
     }
 
     /// Set the value of the gauge with the provided `labels`.
-    pub fn set(&self, labels: &L, value: i64) {
-        self.metric
-            .with_label_values(&labels.label_values())
-            .set(value);
+    pub fn set(&self, labels: &L, value: P::T) {
+        self.metric.get_for(labels).set(value);
     }
 
     /// Add `value` to the gauge with the provided `labels`.
-    pub fn add(&self, labels: &L, value: i64) {
-        self.metric
-            .with_label_values(&labels.label_values())
-            .add(value);
+    pub fn add(&self, labels: &L, value: P::T) {
+        self.metric.get_for(labels).add(value);
     }
 
     /// Subtract `value` from the gauge with the provided `labels`.
-    pub fn sub(&self, labels: &L, value: i64) {
-        self.metric
-            .with_label_values(&labels.label_values())
-            .sub(value);
+    pub fn sub(&self, labels: &L, value: P::T) {
+        self.metric.get_for(labels).sub(value);
     }
 
     /// Increment the gauge by `1`, using the provided `labels` for the event.
     pub fn inc(&self, labels: &L) {
-        self.metric.with_label_values(&labels.label_values()).inc();
+        self.metric.get_for(labels).inc();
     }
 
     /// Decrement the gauge by `1`, using the provided `labels` for the event.
     pub fn dec(&self, labels: &L) {
-        self.metric.with_label_values(&labels.label_values()).dec();
+        self.metric.get_for(labels).dec();
+    }
+
+    /// Increase the gauge by `1` for the provided `labels` while the returned guard exists.
+    ///
+    /// While the guard is held, the increment can be moved to other labels using
+    /// [`GenericGaugeGuardWithLabels::with_labels`].
+    #[must_use]
+    pub fn guarded_inc(&self, labels: L) -> GenericGaugeGuardWithLabels<'_, P, L> {
+        GenericGaugeGuardWithLabels::new(self, P::T::from_i64(1), labels)
+    }
+
+    /// Increase the gauge by `v` for the provided `labels` while the returned guard exists.
+    ///
+    /// While the guard is held, the increment can be moved to other labels using
+    /// [`GenericGaugeGuardWithLabels::with_labels`].
+    #[must_use]
+    pub fn guarded_add(&self, labels: L, v: P::T) -> GenericGaugeGuardWithLabels<'_, P, L> {
+        GenericGaugeGuardWithLabels::new(self, v, labels)
+    }
+
+    /// Set the gauge to `v` for the provided `labels` while the returned guard exists, restoring
+    /// the previous value for those labels afterwards.
+    ///
+    /// See [`GenericGaugeSetGuardWithLabels`] for how nested guards are handled.
+    #[must_use]
+    pub fn guarded_set(&self, labels: L, v: P::T) -> GenericGaugeSetGuardWithLabels<'_, P, L> {
+        GenericGaugeSetGuardWithLabels::new(self, v, labels)
+    }
+
+    /// Return the gauge for the provided `labels` in order to update it, keeping its label set
+    /// from expiring until [`GenericGaugeWithLabels::unpin_series`] is called with the returned
+    /// key.
+    pub(crate) fn pin_series(&self, labels: &L) -> (GenericGauge<P>, Option<u64>) {
+        self.metric.pin_for(labels)
+    }
+
+    /// Release a pin taken by [`GenericGaugeWithLabels::pin_series`].
+    pub(crate) fn unpin_series(&self, key: Option<u64>) {
+        self.metric.unpin(key);
+    }
+
+    /// Return a handle to the gauge for the provided `labels`.
+    ///
+    /// The handle is bound to one label set, so updating it skips the label lookup performed by
+    /// the other methods of `GenericGaugeWithLabels`.
+    ///
+    /// Getting a handle admits `labels` under any cardinality limit, as an update would. If they
+    /// do not fit, the handle is for the overflow series, and each update made through it is
+    /// counted as redirected.
+    ///
+    /// If the label set is later removed, by [`GenericGaugeWithLabels::remove`], by
+    /// [`GenericGaugeWithLabels::reset`] or by expiring after
+    /// [`GenericGaugeWithLabels::with_idle_ttl`], the handle is detached: updates made through it
+    /// are no longer exported.
+    pub fn handle(&self, labels: &L) -> SeriesHandle<GenericGauge<P>> {
+        self.metric.handle_for(labels)
+    }
+}
+
+impl<P: Atomic + 'static, L: FiniteLabels> GenericGaugeWithLabels<P, L> {
+    /// Return a table of handles to the gauge, one for each possible value of `L`.
+    ///
+    /// As with [`GenericGaugeWithLabels::handle`], a handle is detached if its label set is
+    /// removed.
+    pub fn handles(&self) -> HandleTable<L, SeriesHandle<GenericGauge<P>>> {
+        HandleTable::new(|vals| self.metric.handle(vals))
     }
 }
 
@@ -178,51 +1081,155 @@ impl<L: Labels> IntGaugeWithLabels<L> {
 ///
 /// [`Labels`]: trait.Labels.html
 pub struct HistogramWithLabels<L: Labels> {
-    metric: HistogramVec,
+    metric: LabeledVec<HistogramVec>,
     _labels: PhantomData<L>,
 }
 
 impl<L: Labels> HistogramWithLabels<L> {
-    /// Construct and immediately register a new `HistogramWithLabels` instance.
-    pub fn register_new(name: &str, help: &str) -> Self {
-        let metric = register_histogram_vec!(name, help, &L::label_names()).unwrap();
+    /// Construct a new `HistogramWithLabels` instance, without registering it.
+    pub fn new(name: &str, help: &str) -> Result<Self> {
+        Self::from_opts(HistogramOpts::new(name, help))
+    }
+
+    /// Construct a new `HistogramWithLabels` instance, without registering it.
+    ///
+    /// This will use the provided `buckets` when constructing the underlying [`HistogramVec`].
+    pub fn new_with_buckets(name: &str, help: &str, buckets: Vec<f64>) -> Result<Self> {
+        Self::from_opts(HistogramOpts::new(name, help).buckets(buckets))
+    }
+
+    /// Return a builder for a histogram, for setting options like a namespace, constant labels
+    /// or buckets.
+    pub fn builder(name: &str, help: &str) -> LabeledMetricBuilder<Self> {
+        LabeledMetricBuilder::new(name, help)
+    }
+
+    pub(crate) fn from_opts(opts: HistogramOpts) -> Result<Self> {
+        let metric = HistogramVec::new(opts, &L::label_names())?;
 
         // Note: for histograms, like gauges, we don't need to -- and should not! -- prepopulate
         // the metric with the possible labels.
 
-        Self {
-            metric,
+        Ok(Self {
+            metric: LabeledVec::new(metric),
             _labels: PhantomData,
-        }
+        })
+    }
+
+    /// Construct a new `HistogramWithLabels` instance and register it in `registry`.
+    pub fn try_register_in(registry: &Registry, name: &str, help: &str) -> Result<Self> {
+        let histogram = Self::new(name, help)?;
+        histogram.register_in(registry)?;
+        Ok(histogram)
+    }
+
+    /// Construct a new `HistogramWithLabels` instance and register it in `registry`.
+    ///
+    /// This will use the provided `buckets` when constructing the underlying [`HistogramVec`].
+    pub fn try_register_in_with_buckets(
+        registry: &Registry,
+        name: &str,
+        help: &str,
+        buckets: Vec<f64>,
+    ) -> Result<Self> {
+        let histogram = Self::new_with_buckets(name, help, buckets)?;
+        histogram.register_in(registry)?;
+        Ok(histogram)
     }
 
     /// Construct and immediately register a new `HistogramWithLabels` instance.
     ///
-    /// This will use the provided `buckets` when registering the underlying [`HistogramVec`].
-    pub fn register_new_with_buckets(name: &str, help: &str, buckets: Vec<f64>) -> Self {
-        let metric = register_histogram_vec!(name, help, &L::label_names(), buckets).unwrap();
+    /// The metric is registered in the default registry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric cannot be constructed or registered. See
+    /// [`HistogramWithLabels::try_register_in`] for a fallible alternative.
+    pub fn register_new(name: &str, help: &str) -> Self {
+        Self::try_register_in(prometheus::default_registry(), name, help).unwrap()
+    }
 
-        // Note: for histograms, like gauges, we don't need to -- and should not! -- prepopulate
-        // the metric with the possible labels.
+    /// Limit the number of distinct label sets this histogram may contain.
+    ///
+    /// Once `max_series` distinct label sets have been used, events for any other label set are
+    /// recorded with every label set to [`OVERFLOW_LABEL_VALUE`] instead, and `overflow` is
+    /// incremented once for each redirected event. The label sets in
+    /// [`Labels::possible_label_values`] are admitted first, and count towards the limit.
+    ///
+    /// [`OVERFLOW_LABEL_VALUE`]: constant.OVERFLOW_LABEL_VALUE.html
+    pub fn with_cardinality_limit(mut self, max_series: usize, overflow: IntCounter) -> Self {
+        self.metric.limit_cardinality::<L>(max_series, overflow);
+        self
+    }
 
-        Self {
-            metric,
-            _labels: PhantomData,
-        }
+    /// Remove label sets that have not been updated within `ttl`.
+    ///
+    /// Idle label sets are removed whenever the histogram is gathered from a registry, or when
+    /// [`HistogramWithLabels::sweep`] is called. This applies whether the histogram is registered
+    /// before or after this is called.
+    ///
+    /// Updates made through a handle from [`HistogramWithLabels::handle`] do not count as activity,
+    /// and a handle whose label set is removed is detached: updates made through it afterwards are
+    /// no longer exported.
+    pub fn with_idle_ttl(self, ttl: Duration) -> Self {
+        self.metric.expire_after(ttl);
+        self
+    }
+
+    /// Register this histogram in `registry`.
+    pub fn register_in(&self, registry: &Registry) -> Result<()> {
+        self.metric.register(registry)
+    }
+
+    /// Remove the histogram for the provided `labels`, returning whether it existed.
+    ///
+    /// Handles for the removed label set are no longer exported, even if they are updated.
+    pub fn remove(&self, labels: &L) -> bool {
+        self.metric.remove_for(labels)
+    }
+
+    /// Remove the histogram for every label set.
+    pub fn reset(&self) {
+        self.metric.reset::<L>();
+    }
+
+    /// Remove label sets that have been idle for longer than the TTL set by
+    /// [`HistogramWithLabels::with_idle_ttl`], returning the number of label sets removed.
+    pub fn sweep(&self) -> usize {
+        self.metric.sweep()
+    }
+
+    /// Construct and immediately register a new `HistogramWithLabels` instance.
+    ///
+    /// This will use the provided `buckets` when registering the underlying [`HistogramVec`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric cannot be constructed or registered. See
+    /// [`HistogramWithLabels::try_register_in_with_buckets`] for a fallible alternative.
+    pub fn register_new_with_buckets(name: &str, help: &str, buckets: Vec<f64>) -> Self {
+        Self::try_register_in_with_buckets(prometheus::default_registry(), name, help, buckets)
+            .unwrap()
     }
 
     /// Add a single observation to the histogram with the provided `labels`.
     pub fn observe(&self, labels: &L, value: f64) {
-        self.metric
-            .with_label_values(&labels.label_values())
-            .observe(value);
+        self.metric.get_for(labels).observe(value);
     }
 
     /// Return a [`HistogramTimer`] to track a duration, using the provided `labels`.
     pub fn start_timer(&self, labels: &L) -> HistogramTimer {
-        self.metric
-            .with_label_values(&labels.label_values())
-            .start_timer()
+        self.metric.get_for(labels).start_timer()
+    }
+
+    /// Creates a guard value that will observe the elapsed time, in seconds, using the provided
+    /// `labels`, once dropped.
+    ///
+    /// Prior to dropping, the labels can be altered using
+    /// [`DeferredObserveWithLabels::with_labels`].
+    #[must_use]
+    pub fn start_deferred_timer(&self, labels: L) -> DeferredObserveWithLabels<'_, L> {
+        DeferredObserveWithLabels::new(self, labels)
     }
 
     /// Observe execution time of a closure, in seconds.
@@ -230,22 +1237,448 @@ impl<L: Labels> HistogramWithLabels<L> {
     where
         F: FnOnce() -> T,
     {
-        self.metric
-            .with_label_values(&labels.label_values())
-            .observe_closure_duration(f)
+        self.metric.get_for(labels).observe_closure_duration(f)
     }
 
     /// Return accumulated sum of all samples, using the provided `labels`.
     pub fn get_sample_sum(&self, labels: &L) -> f64 {
-        self.metric
-            .with_label_values(&labels.label_values())
-            .get_sample_sum()
+        self.metric.peek_for(labels).get_sample_sum()
     }
 
     /// Return count of all samples, using the provided `labels`.
     pub fn get_sample_count(&self, labels: &L) -> u64 {
-        self.metric
-            .with_label_values(&labels.label_values())
-            .get_sample_count()
+        self.metric.peek_for(labels).get_sample_count()
+    }
+
+    /// Return a handle to the histogram for the provided `labels`.
+    ///
+    /// The handle is bound to one label set, so updating it skips the label lookup performed by
+    /// the other methods of `HistogramWithLabels`.
+    ///
+    /// Getting a handle admits `labels` under any cardinality limit, as an update would. If they
+    /// do not fit, the handle is for the overflow series, and each update made through it is
+    /// counted as redirected.
+    ///
+    /// If the label set is later removed, by [`HistogramWithLabels::remove`], by
+    /// [`HistogramWithLabels::reset`] or by expiring after [`HistogramWithLabels::with_idle_ttl`],
+    /// the handle is detached: updates made through it are no longer exported.
+    pub fn handle(&self, labels: &L) -> SeriesHandle<Histogram> {
+        self.metric.handle_for(labels)
+    }
+}
+
+impl<L: FiniteLabels> HistogramWithLabels<L> {
+    /// Return a table of handles to the histogram, one for each possible value of `L`.
+    ///
+    /// As with [`HistogramWithLabels::handle`], a handle is detached if its label set is removed.
+    pub fn handles(&self) -> HandleTable<L, SeriesHandle<Histogram>> {
+        HandleTable::new(|vals| self.metric.handle(vals))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        cartesian_product, CounterWithLabels, FiniteLabels, GaugeWithLabels, HistogramWithLabels,
+        IntCounterWithLabels, IntGaugeWithLabels, LabelBuffer, LabelValues, Labels,
+        INLINE_LABEL_VALUES,
+    };
+    use crate::{error::Error, TimingBucket, OVERFLOW_LABEL_VALUE};
+    use prometheus::{core::Collector, IntCounter, Registry};
+    use std::time::Duration;
+
+    #[test]
+    fn cartesian_product_is_row_major() {
+        assert_eq!(
+            cartesian_product(vec![vec!["a", "b"], vec!["x", "y", "z"]]),
+            vec![
+                vec!["a", "x"],
+                vec!["a", "y"],
+                vec!["a", "z"],
+                vec!["b", "x"],
+                vec!["b", "y"],
+                vec!["b", "z"],
+            ]
+        );
+    }
+
+    #[test]
+    fn label_buffers_spill_to_the_heap() {
+        let mut buf = LabelBuffer::new();
+        let vals: Vec<String> = (0..INLINE_LABEL_VALUES + 2)
+            .map(|i| i.to_string())
+            .collect();
+        let vals: Vec<&str> = vals.iter().map(String::as_str).collect();
+
+        buf.extend(&vals[..INLINE_LABEL_VALUES - 1]);
+        assert!(buf.spilled.is_empty());
+        buf.extend(&vals[INLINE_LABEL_VALUES - 1..]);
+        assert!(!buf.spilled.is_empty());
+        assert_eq!(buf.as_slice(), vals.as_slice());
+    }
+
+    #[test]
+    fn cartesian_product_of_unknown_values_is_empty() {
+        assert!(cartesian_product(vec![vec!["a", "b"], vec![]]).is_empty());
+    }
+
+    #[test]
+    fn duplicate_registrations_are_reported() {
+        let registry = Registry::new();
+        IntCounterWithLabels::<TimingBucket>::try_register_in(&registry, "dup", "a counter")
+            .unwrap();
+
+        let err = IntGaugeWithLabels::<TimingBucket>::try_register_in(&registry, "dup", "a gauge")
+            .err()
+            .expect("name is already registered");
+        assert!(matches!(
+            err,
+            Error::Prometheus(prometheus::Error::AlreadyReg)
+        ));
+
+        // The same name is free in a different registry.
+        HistogramWithLabels::<TimingBucket>::try_register_in(
+            &Registry::new(),
+            "dup",
+            "a histogram",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn handles_share_the_labeled_series() {
+        let counter = IntCounterWithLabels::<TimingBucket>::new("handles", "help").unwrap();
+        let handle = counter.handle(&TimingBucket::P50);
+        handle.inc();
+        counter.inc(&TimingBucket::P50);
+        assert_eq!(handle.get(), 2);
+
+        let table = counter.handles();
+        table.get(&TimingBucket::P99).inc_by(3);
+        table.get(&TimingBucket::Count).inc();
+        assert_eq!(counter.handle(&TimingBucket::P99).get(), 3);
+        assert_eq!(counter.handle(&TimingBucket::Count).get(), 1);
+        assert_eq!(table.get(&TimingBucket::P50).get(), 2);
+    }
+
+    struct Tenant(&'static str);
+
+    impl Labels for Tenant {
+        fn label_names() -> Vec<&'static str> {
+            vec!["tenant"]
+        }
+        fn possible_label_values() -> Vec<LabelValues<'static>> {
+            vec![vec!["default"]]
+        }
+        fn label_values(&self) -> LabelValues<'_> {
+            vec![self.0]
+        }
+    }
+
+    #[test]
+    fn cardinality_limit_redirects_new_label_sets() {
+        let overflow = IntCounter::new("overflow", "help").unwrap();
+        let counter = IntCounterWithLabels::<Tenant>::new("limited", "help")
+            .unwrap()
+            .with_cardinality_limit(2, overflow.clone());
+
+        counter.inc(&Tenant("a"));
+        counter.inc(&Tenant("b"));
+        counter.inc(&Tenant("c"));
+        counter.inc(&Tenant("a"));
+        counter.inc(&Tenant("default"));
+
+        // `default` is a possible label value, so it was admitted up front along with `a`.
+        assert_eq!(overflow.get(), 2);
+        assert_eq!(counter.handle(&Tenant("default")).get(), 1);
+        assert_eq!(counter.handle(&Tenant("a")).get(), 2);
+        assert_eq!(counter.handle(&Tenant(OVERFLOW_LABEL_VALUE)).get(), 2);
+        assert_eq!(overflow.get(), 2);
+    }
+
+    #[test]
+    fn reads_do_not_admit_label_sets() {
+        let overflow = IntCounter::new("read_overflow", "help").unwrap();
+        let histogram = HistogramWithLabels::<Tenant>::new("read_limited", "help")
+            .unwrap()
+            .with_cardinality_limit(2, overflow.clone());
+
+        // Unadmitted label sets are read from the overflow series, without taking up a slot.
+        assert_eq!(histogram.get_sample_count(&Tenant("a")), 0);
+        assert_eq!(histogram.get_sample_sum(&Tenant("b")), 0.0);
+        assert_eq!(overflow.get(), 0);
+
+        // `default` and `a` fill the limit, so `b` is redirected to the overflow series.
+        histogram.observe(&Tenant("a"), 1.0);
+        histogram.observe(&Tenant("b"), 1.0);
+        assert_eq!(overflow.get(), 1);
+        assert_eq!(histogram.get_sample_count(&Tenant("a")), 1);
+        assert_eq!(histogram.get_sample_count(&Tenant("b")), 1);
+        assert_eq!(histogram.get_sample_sum(&Tenant("c")), 1.0);
+        assert_eq!(overflow.get(), 1);
+    }
+
+    #[test]
+    fn handles_admit_label_sets_and_count_overflowing_updates() {
+        let overflow = IntCounter::new("handle_overflow", "help").unwrap();
+        let counter = IntCounterWithLabels::<Tenant>::new("handle_limited", "help")
+            .unwrap()
+            .with_cardinality_limit(2, overflow.clone());
+
+        // `default` and `a` fill the limit, so the handle for `b` is for the overflow series.
+        let a = counter.handle(&Tenant("a"));
+        let b = counter.handle(&Tenant("b"));
+        a.inc();
+        a.inc();
+        b.inc();
+        b.inc_by(2);
+        assert_eq!(counter.handle(&Tenant("a")).get(), 2);
+        assert_eq!(counter.handle(&Tenant(OVERFLOW_LABEL_VALUE)).get(), 3);
+        assert_eq!(overflow.get(), 2);
+    }
+
+    #[test]
+    fn possible_values_are_admitted_without_counting_overflow() {
+        let overflow = IntCounter::new("seed_overflow", "help").unwrap();
+        let counter = IntCounterWithLabels::<Method>::new("seed_limited", "help")
+            .unwrap()
+            .with_cardinality_limit(1, overflow.clone());
+        assert_eq!(overflow.get(), 0);
+
+        counter.reset();
+        assert_eq!(overflow.get(), 0);
+    }
+
+    #[test]
+    fn series_can_be_removed_and_reset() {
+        let gauge = IntGaugeWithLabels::<Tenant>::new("removable", "help").unwrap();
+        gauge.set(&Tenant("a"), 4);
+        gauge.set(&Tenant("b"), 2);
+        assert_eq!(gauge.metric.vec.collect()[0].get_metric().len(), 2);
+
+        assert!(gauge.remove(&Tenant("a")));
+        assert!(!gauge.remove(&Tenant("a")));
+        assert_eq!(gauge.metric.vec.collect()[0].get_metric().len(), 1);
+
+        gauge.reset();
+        assert!(gauge.metric.vec.collect()[0].get_metric().is_empty());
+
+        // Counters are re-initialized with their possible label values.
+        let counter = IntCounterWithLabels::<Tenant>::new("resettable", "help").unwrap();
+        counter.inc(&Tenant("a"));
+        counter.reset();
+        let family = counter.metric.vec.collect();
+        assert_eq!(family[0].get_metric().len(), 1);
+        assert_eq!(counter.handle(&Tenant("default")).get(), 0);
+    }
+
+    #[test]
+    fn idle_series_expire_at_gather_time() {
+        let registry = Registry::new();
+        let gauge = IntGaugeWithLabels::<Tenant>::new("expiring", "help")
+            .unwrap()
+            .with_idle_ttl(Duration::from_millis(50));
+        gauge.register_in(&registry).unwrap();
+
+        gauge.set(&Tenant("stale"), 1);
+        std::thread::sleep(Duration::from_millis(60));
+        gauge.set(&Tenant("fresh"), 1);
+
+        let families = registry.gather();
+        let metrics = families[0].get_metric();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].get_label()[0].get_value(), "fresh");
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(gauge.sweep(), 1);
+        assert_eq!(gauge.sweep(), 0);
+    }
+
+    #[test]
+    fn idle_ttls_apply_to_metrics_registered_earlier() {
+        let registry = Registry::new();
+        let counter = IntCounterWithLabels::<Tenant>::try_register_in(&registry, "late", "help")
+            .unwrap()
+            .with_idle_ttl(Duration::from_millis(50));
+
+        counter.inc(&Tenant("stale"));
+        std::thread::sleep(Duration::from_millis(60));
+        let families = registry.gather();
+        let tenants: Vec<_> = families[0]
+            .get_metric()
+            .iter()
+            .map(|m| m.get_label()[0].get_value())
+            .collect();
+        assert_eq!(tenants, ["default"]);
+    }
+
+    #[test]
+    fn gauge_guards_follow_their_labels() {
+        let gauge = IntGaugeWithLabels::<TimingBucket>::new("guarded", "help").unwrap();
+        let value = |labels| gauge.handle(&labels).get();
+
+        let guard = gauge.guarded_add(TimingBucket::P50, 3);
+        let other = gauge.guarded_inc(TimingBucket::P50);
+        assert_eq!(value(TimingBucket::P50), 4);
+
+        let guard = guard.with_labels(TimingBucket::P99);
+        assert_eq!(value(TimingBucket::P50), 1);
+        assert_eq!(value(TimingBucket::P99), 3);
+
+        drop(guard);
+        drop(other);
+        assert_eq!(value(TimingBucket::P50), 0);
+        assert_eq!(value(TimingBucket::P99), 0);
+    }
+
+    #[test]
+    fn gauge_guards_decrement_the_series_they_incremented() {
+        let overflow = IntCounter::new("guard_overflow", "help").unwrap();
+        let gauge = IntGaugeWithLabels::<Tenant>::new("guarded_limited", "help")
+            .unwrap()
+            .with_cardinality_limit(2, overflow)
+            .with_idle_ttl(Duration::from_millis(10));
+        let value = |tenant| gauge.handle(&Tenant(tenant)).get();
+
+        gauge.set(&Tenant("a"), 0);
+        // The limit is full, so the guard increments the overflow series...
+        let guard = gauge.guarded_inc(Tenant("b"));
+        assert_eq!(value(OVERFLOW_LABEL_VALUE), 1);
+        // Series with live guards do not expire.
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(gauge.sweep(), 1);
+        assert_eq!(value(OVERFLOW_LABEL_VALUE), 1);
+
+        // A slot freed while the guard is held does not move its decrement.
+        gauge.set(&Tenant("b"), 5);
+
+        drop(guard);
+        assert_eq!(value(OVERFLOW_LABEL_VALUE), 0);
+        assert_eq!(value("b"), 5);
+    }
+
+    #[test]
+    fn gauge_guard_labels_can_be_edited_in_place() {
+        let gauge = IntGaugeWithLabels::<TimingBucket>::new("guard_labels_mut", "help").unwrap();
+        let value = |labels| gauge.handle(&labels).get();
+
+        let mut guard = gauge.guarded_inc(TimingBucket::P50);
+        *guard.labels_mut() = TimingBucket::P99;
+        assert_eq!(guard.labels().as_str(), "p99");
+        assert_eq!(value(TimingBucket::P50), 0);
+        assert_eq!(value(TimingBucket::P99), 1);
+
+        drop(guard);
+        assert_eq!(value(TimingBucket::P99), 0);
+    }
+
+    #[test]
+    fn deferred_timers_observe_into_the_final_labels() {
+        let histogram = HistogramWithLabels::<TimingBucket>::new("latency", "help").unwrap();
+        let count = |bucket| histogram.get_sample_count(&bucket);
+
+        let mut timer = histogram.start_deferred_timer(TimingBucket::P50);
+        *timer.labels_mut() = TimingBucket::P99;
+        timer.with_labels(TimingBucket::Max).complete_observe();
+        assert_eq!(count(TimingBucket::P50), 0);
+        assert_eq!(count(TimingBucket::P99), 0);
+        assert_eq!(count(TimingBucket::Max), 1);
+
+        let mut timer = histogram.start_deferred_timer(TimingBucket::Max);
+        timer.cancel();
+        drop(timer);
+        assert_eq!(count(TimingBucket::Max), 1);
+    }
+
+    #[test]
+    fn outcome_counters_record_how_guards_end() {
+        use crate::Outcome;
+
+        let counter =
+            IntCounterWithLabels::<(TimingBucket, Outcome)>::new("requests", "help").unwrap();
+        let count = |outcome| counter.handle(&(TimingBucket::P50, outcome)).get();
+
+        counter
+            .deferred_inc_with_outcome(TimingBucket::P50)
+            .complete();
+        assert_eq!(count(Outcome::Success), 1);
+
+        let early_return = || -> Result<(), ()> {
+            let _guard = counter.deferred_inc_with_outcome(TimingBucket::P50);
+            Err(())?;
+            unreachable!()
+        };
+        assert!(early_return().is_err());
+        assert_eq!(count(Outcome::Cancelled), 1);
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = counter.deferred_add_with_outcome(2, TimingBucket::P50);
+            panic!("boom");
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(count(Outcome::Panicked), 2);
+        assert_eq!(count(Outcome::Success), 1);
+    }
+
+    #[test]
+    fn float_metrics_share_the_labeled_api() {
+        let counter = CounterWithLabels::<TimingBucket>::new("cpu_seconds", "help").unwrap();
+        counter.add(1.5, &TimingBucket::P50);
+        counter.deferred_add(0.25, TimingBucket::P50).complete_add();
+        assert_eq!(counter.handle(&TimingBucket::P50).get(), 1.75);
+        assert_eq!(counter.handle(&TimingBucket::P99).get(), 0.0);
+
+        let gauge = GaugeWithLabels::<TimingBucket>::new("ratio", "help").unwrap();
+        gauge.set(&TimingBucket::P50, 0.5);
+        {
+            let _guard = gauge.guarded_add(TimingBucket::P50, 0.25);
+            assert_eq!(gauge.handle(&TimingBucket::P50).get(), 0.75);
+        }
+        assert_eq!(gauge.handle(&TimingBucket::P50).get(), 0.5);
+    }
+
+    crate::label_enum! {
+        #[label = "method"]
+        enum Method {
+            Get,
+            Post,
+        }
+    }
+
+    #[test]
+    fn tuples_combine_their_labels() {
+        type Combined = (Method, TimingBucket);
+        assert_eq!(Combined::label_names(), vec!["method", "bucket"]);
+
+        let possible = Combined::possible_label_values();
+        assert_eq!(possible.len(), Combined::CARDINALITY);
+        assert_eq!(possible[0], vec!["get", "p25"]);
+        assert_eq!(possible[TimingBucket::COUNT], vec!["post", "p25"]);
+
+        let labels = (Method::Post, TimingBucket::P99);
+        assert_eq!(labels.label_values(), vec!["post", "p99"]);
+        assert_eq!(possible[labels.label_index()], labels.label_values());
+        labels.visit_label_values(|vals| assert_eq!(vals, ["post", "p99"]));
+
+        let counter = IntCounterWithLabels::<Combined>::new("combined", "help").unwrap();
+        counter.handles().get(&labels).inc();
+        assert_eq!(counter.handle(&labels).get(), 1);
+    }
+
+    #[test]
+    fn unit_labels_produce_a_single_series() {
+        let counter = IntCounterWithLabels::<()>::new("unlabeled", "help").unwrap();
+        counter.inc(&());
+        assert_eq!(counter.handle(&()).get(), 1);
+        assert_eq!(counter.handles().get(&()).get(), 1);
+
+        let families = counter.metric.vec.collect();
+        assert_eq!(families[0].get_metric().len(), 1);
+        assert!(families[0].get_metric()[0].get_label().is_empty());
+    }
+
+    #[test]
+    fn invalid_names_are_reported() {
+        assert!(IntCounterWithLabels::<TimingBucket>::new("not a valid name", "help").is_err());
     }
 }
@@ -0,0 +1,232 @@
+//! Tracking of live gauge guards, to find guards that are leaked instead of dropped.
+//!
+//! This module is only compiled with the `leak-detection` feature.
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::{
+    backtrace::Backtrace,
+    cmp::Reverse,
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+lazy_static! {
+    /// Every live tracked guard, keyed by its identifier.
+    static ref LIVE_GUARDS: Mutex<HashMap<u64, GuardRecord>> = Mutex::new(HashMap::new());
+}
+
+/// The source of unique identifiers for tracked guards.
+static NEXT_GUARD_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Whether a backtrace is captured when a guard is created.
+static CAPTURE_BACKTRACES: AtomicBool = AtomicBool::new(false);
+
+struct GuardRecord {
+    metric: String,
+    labels: Vec<(String, String)>,
+    created: Instant,
+    backtrace: Option<Arc<Backtrace>>,
+}
+
+/// A gauge guard that has not been dropped yet.
+///
+/// Returned by [`live_guards_older_than`].
+#[derive(Clone, Debug)]
+pub struct LiveGuard {
+    /// The name of the gauge the guard was created for.
+    pub metric: String,
+    /// The label names and values of the gauge the guard was created for.
+    pub labels: Vec<(String, String)>,
+    /// How long ago the guard was created.
+    pub age: Duration,
+    /// Where the guard was created, if backtraces were being captured at the time.
+    ///
+    /// See [`capture_guard_backtraces`].
+    pub backtrace: Option<Arc<Backtrace>>,
+}
+
+impl fmt::Display for LiveGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "guard for `{}`", self.metric)?;
+        if !self.labels.is_empty() {
+            let labels: Vec<_> = self
+                .labels
+                .iter()
+                .map(|(name, value)| format!("{}=\"{}\"", name, value))
+                .collect();
+            write!(f, "{{{}}}", labels.join(","))?;
+        }
+        write!(f, " alive for {:?}", self.age)?;
+        if let Some(backtrace) = &self.backtrace {
+            write!(f, ", created at:\n{}", backtrace)?;
+        }
+        Ok(())
+    }
+}
+
+/// Capture a backtrace whenever a gauge guard is created, or stop doing so.
+///
+/// Backtraces are not captured by default, because capturing them is expensive. Guards created
+/// while capturing is enabled report their backtrace in [`LiveGuard::backtrace`].
+pub fn capture_guard_backtraces(enabled: bool) {
+    CAPTURE_BACKTRACES.store(enabled, Ordering::SeqCst);
+}
+
+/// List the gauge guards that have been alive for longer than `threshold`, oldest first.
+pub fn live_guards_older_than(threshold: Duration) -> Vec<LiveGuard> {
+    live_guards(|guard| guard.age > threshold)
+}
+
+/// List the live gauge guards matching `filter`, oldest first.
+fn live_guards(filter: impl Fn(&LiveGuard) -> bool) -> Vec<LiveGuard> {
+    let now = Instant::now();
+    let mut guards: Vec<_> = LIVE_GUARDS
+        .lock()
+        .values()
+        .map(|record| LiveGuard {
+            metric: record.metric.clone(),
+            labels: record.labels.clone(),
+            age: now.saturating_duration_since(record.created),
+            backtrace: record.backtrace.clone(),
+        })
+        .filter(filter)
+        .collect();
+    guards.sort_by_key(|guard| Reverse(guard.age));
+    guards
+}
+
+/// The number of live guards for the gauge named `metric`, across all of its label sets.
+pub fn live_guard_count(metric: &str) -> usize {
+    LIVE_GUARDS
+        .lock()
+        .values()
+        .filter(|record| record.metric == metric)
+        .count()
+}
+
+/// Assert that every guard for the gauge named `metric` has been dropped.
+///
+/// # Panics
+///
+/// Panics if there are live guards for the gauge, listing each of them.
+#[track_caller]
+pub fn assert_no_live_guards(metric: &str) {
+    let live = live_guards(|guard| guard.metric == metric);
+    if !live.is_empty() {
+        let guards: Vec<_> = live.iter().map(LiveGuard::to_string).collect();
+        panic!(
+            "{} live guard(s) for `{}`:\n{}",
+            live.len(),
+            metric,
+            guards.join("\n")
+        );
+    }
+}
+
+/// Registers a live guard while it exists. Held by each tracked guard.
+pub(crate) struct TrackedGuard {
+    id: u64,
+}
+
+impl TrackedGuard {
+    pub(crate) fn new(metric: &str, labels: Vec<(String, String)>) -> Self {
+        let id = NEXT_GUARD_ID.fetch_add(1, Ordering::Relaxed);
+        let backtrace = if CAPTURE_BACKTRACES.load(Ordering::SeqCst) {
+            Some(Arc::new(Backtrace::force_capture()))
+        } else {
+            None
+        };
+        let record = GuardRecord {
+            metric: metric.to_owned(),
+            labels,
+            created: Instant::now(),
+            backtrace,
+        };
+        LIVE_GUARDS.lock().insert(id, record);
+        Self { id }
+    }
+
+    /// Update the labels reported for this guard.
+    pub(crate) fn set_labels(&mut self, labels: Vec<(String, String)>) {
+        if let Some(record) = LIVE_GUARDS.lock().get_mut(&self.id) {
+            record.labels = labels;
+        }
+    }
+}
+
+impl Drop for TrackedGuard {
+    fn drop(&mut self) {
+        LIVE_GUARDS.lock().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        assert_no_live_guards, capture_guard_backtraces, live_guard_count, live_guards,
+        live_guards_older_than,
+    };
+    use crate::{GuardedGauge, IntGaugeWithLabels, TimingBucket};
+    use prometheus::IntGauge;
+    use std::time::Duration;
+
+    #[test]
+    fn leaked_guards_are_reported() {
+        let gauge = IntGauge::new("leaky_gauge", "help").unwrap();
+        let guard = gauge.guarded_inc();
+        assert_eq!(live_guard_count("leaky_gauge"), 1);
+
+        std::mem::forget(gauge.guarded_inc());
+        std::thread::sleep(Duration::from_millis(10));
+        let old: Vec<_> = live_guards_older_than(Duration::from_millis(5))
+            .into_iter()
+            .filter(|guard| guard.metric == "leaky_gauge")
+            .collect();
+        assert_eq!(old.len(), 2);
+
+        drop(guard);
+        assert_eq!(live_guard_count("leaky_gauge"), 1);
+        let panic = std::panic::catch_unwind(|| assert_no_live_guards("leaky_gauge"));
+        assert!(panic.is_err());
+    }
+
+    #[test]
+    fn leaked_set_guards_are_reported() {
+        let gauge = IntGauge::new("leaky_mode", "help").unwrap();
+        drop(gauge.guarded_set(1));
+        assert_no_live_guards("leaky_mode");
+
+        std::mem::forget(gauge.guarded_set(2));
+        assert_eq!(live_guard_count("leaky_mode"), 1);
+
+        let labeled =
+            IntGaugeWithLabels::<TimingBucket>::new("leaky_labeled_mode", "help").unwrap();
+        std::mem::forget(labeled.guarded_set(TimingBucket::P50, 1));
+        let live = live_guards(|guard| guard.metric == "leaky_labeled_mode");
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].labels, [("bucket".to_owned(), "p50".to_owned())]);
+    }
+
+    #[test]
+    fn labeled_guards_report_their_current_labels() {
+        let gauge = IntGaugeWithLabels::<TimingBucket>::new("leaky_labeled", "help").unwrap();
+        capture_guard_backtraces(true);
+        let mut guard = gauge.guarded_inc(TimingBucket::P50);
+        capture_guard_backtraces(false);
+        guard.set_labels(TimingBucket::P99);
+
+        let live = live_guards(|guard| guard.metric == "leaky_labeled");
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].labels, [("bucket".to_owned(), "p99".to_owned())]);
+        assert!(live[0].backtrace.is_some());
+
+        drop(guard);
+        assert_no_live_guards("leaky_labeled");
+    }
+}
@@ -3,10 +3,17 @@
 //! This crate builds on the Promtheus crate to provide API with additional safety guardrails:
 //!
 //! * Use [`InstrumentedFuture`] to easily instrument futures with metric updates.
+//! * Enable the `futures` feature to instrument streams in the same way, with
+//!   `InstrumentedStream`.
 //! * Use [`GuardedGauge`] to work with gauges using an RAII-style guard that decrements
 //!   the gauge upon drop.
-//! * Use [`IntCounterWithLabels`] and [`IntGaugeWithLabels`] to produce labeled Prometheus
+//! * Use [`IntCounterWithLabels`] and [`IntGaugeWithLabels`] (or their floating point
+//!   counterparts, [`CounterWithLabels`] and [`GaugeWithLabels`]) to produce labeled Prometheus
 //!   metrics with a type-safe API.
+//! * Enable the `derive` feature to implement [`Labels`] for label structs with
+//!   `#[derive(Labels)]`.
+//! * Enable the `leak-detection` feature to track live gauge guards, to find guards that are
+//!   leaked instead of dropped. See `live_guards_older_than` and `assert_no_live_guards`.
 
 // When building the project in release mode:
 //   (1): Promote warnings into errors.
@@ -16,26 +23,84 @@
 //   (5): Promote warnings in examples into errors, except for unused variables.
 #![cfg_attr(not(debug_assertions), deny(warnings))]
 #![cfg_attr(not(debug_assertions), warn(missing_docs))]
-#![cfg_attr(not(debug_assertions), deny(broken_intra_doc_links))]
-#![cfg_attr(not(debug_assertions), deny(invalid_codeblock_attributes))]
+#![cfg_attr(not(debug_assertions), deny(rustdoc::broken_intra_doc_links))]
+#![cfg_attr(not(debug_assertions), deny(rustdoc::invalid_codeblock_attributes))]
 #![cfg_attr(not(debug_assertions), doc(test(attr(deny(warnings)))))]
 #![cfg_attr(not(debug_assertions), doc(test(attr(allow(dead_code)))))]
 #![cfg_attr(not(debug_assertions), doc(test(attr(allow(unused_variables)))))]
 
+mod builder;
+mod cardinality;
+mod error;
+mod expiry;
 mod guards;
+mod info;
 mod instrumented_future;
+#[cfg(feature = "futures")]
+mod instrumented_stream;
 mod labels;
+#[cfg(feature = "leak-detection")]
+mod leaks;
+mod peak;
 mod percentile;
+mod state;
 
+pub use builder::LabeledMetricBuilder;
+pub use cardinality::OVERFLOW_LABEL_VALUE;
+pub use error::{Error, ParseLabelError, Result};
 pub use guards::{
-    DeferredAdd, DeferredAddWithLabels, DeferredCounter, GaugeGuard, GenericGaugeGuard,
-    GuardedGauge, IntGaugeGuard,
+    DeferredAdd, DeferredAddWithLabels, DeferredAddWithOutcome, DeferredCounter,
+    DeferredObserveWithLabels, GaugeGuard, GaugeGuardLabelsMut, GaugeGuardWithLabels,
+    GaugeSetGuard, GaugeSetGuardWithLabels, GenericGaugeGuard, GenericGaugeGuardWithLabels,
+    GenericGaugeSetGuard, GenericGaugeSetGuardWithLabels, GuardedGauge, IntGaugeGuard,
+    IntGaugeGuardWithLabels, IntGaugeSetGuard, IntGaugeSetGuardWithLabels, Outcome,
 };
-pub use instrumented_future::{InstrumentedFuture, IntoInstrumentedFuture};
+pub use info::InfoMetric;
+pub use instrumented_future::{Completion, ErrorLabel, InstrumentedFuture, IntoInstrumentedFuture};
+#[cfg(feature = "futures")]
+pub use instrumented_stream::{InstrumentedStream, IntoInstrumentedStream};
 pub use labels::{
-    HistogramWithLabels, IntCounterWithLabels, IntGaugeWithLabels, LabelValues, Labels,
+    CounterWithLabels, FiniteLabels, GaugeWithLabels, GenericCounterWithLabels,
+    GenericGaugeWithLabels, HandleTable, HistogramWithLabels, IntCounterWithLabels,
+    IntGaugeWithLabels, LabelValue, LabelValues, Labels, SeriesHandle,
 };
+#[cfg(feature = "leak-detection")]
+pub use leaks::{
+    assert_no_live_guards, capture_guard_backtraces, live_guard_count, live_guards_older_than,
+    LiveGuard,
+};
+pub use peak::PeakGauge;
 pub use percentile::{Observations, Sample, TimingBucket, Windowing};
+pub use state::{StateGauge, StateGuard, StateSet};
+
+/// Derive macro for the [`Labels`] trait.
+///
+/// Each named field of the struct becomes a label, with the field name as the label name and the
+/// field's [`LabelValue`] as the label value. For example:
+///
+/// ```ignore
+/// label_enum! {
+///     pub enum Method {
+///         Get,
+///         Post,
+///     }
+/// }
+///
+/// #[derive(Labels)]
+/// pub struct RequestLabels {
+///     method: Method,
+///     cached: bool,
+/// }
+/// ```
+///
+/// Here, `label_names` is `["method", "cached"]`, and `possible_label_values` contains all four
+/// combinations of `method` and `cached`. If any field has no finite set of values (a `String`,
+/// for instance), `possible_label_values` is empty.
+#[cfg(feature = "derive")]
+pub use prometheus_utils_derive::Labels;
+
+#[doc(hidden)]
+pub use labels::cartesian_product as __cartesian_product;
 
 #[allow(missing_docs)]
 pub mod paste_crate {
@@ -44,27 +109,147 @@ pub mod paste_crate {
 
 // See `label_enum!` below; the factoring into two macros is to accomodate parsing
 // multiple kinds of visibility annotations.
+//
+// The attributes of the enum, and then its variants, are parsed one token tree at a time, so
+// that `#[label = "..."]` attributes can be told apart from other attributes. Each parsed variant
+// is accumulated as `([attributes] Variant [label])`, where `label` is empty unless overridden.
 #[macro_export]
 #[doc(hidden)]
 macro_rules! __label_enum_internal {
-    ($(#[$attr:meta])* ($($vis:tt)*) enum $N:ident { $($(#[$var_attr:meta])* $V:ident),* }) => {
-        $(#[$attr])* $($vis)* enum $N { $($(#[$var_attr])* $V),* }
+    (@attrs [$($kept:tt)*] [$($name:tt)*] $vis:tt $N:ident $body:tt
+        #[label = $l:literal] $($rest:tt)*) => {
+        $crate::__label_enum_internal!(@attrs [$($kept)*] [$l] $vis $N $body $($rest)*);
+    };
+    (@attrs [$($kept:tt)*] [$($name:tt)*] $vis:tt $N:ident $body:tt
+        #[$($a:tt)*] $($rest:tt)*) => {
+        $crate::__label_enum_internal!(
+            @attrs [$($kept)* #[$($a)*]] [$($name)*] $vis $N $body $($rest)*
+        );
+    };
+    (@attrs $attrs:tt $name:tt ($($vis:tt)*) $N:ident { $($body:tt)* }) => {
+        $crate::__label_enum_internal!(@variants $attrs [$($vis)*] $name $N [] [] [] $($body)*);
+    };
+    (@variants $attrs:tt $vis:tt $name:tt $N:ident [$($done:tt)*] [$($var_attr:tt)*]
+        [$($label:tt)*] #[label = $l:literal] $($rest:tt)*) => {
+        $crate::__label_enum_internal!(
+            @variants $attrs $vis $name $N [$($done)*] [$($var_attr)*] [$l] $($rest)*
+        );
+    };
+    (@variants $attrs:tt $vis:tt $name:tt $N:ident [$($done:tt)*] [$($var_attr:tt)*]
+        [$($label:tt)*] #[$($a:tt)*] $($rest:tt)*) => {
+        $crate::__label_enum_internal!(
+            @variants $attrs $vis $name $N [$($done)*] [$($var_attr)* #[$($a)*]] [$($label)*]
+            $($rest)*
+        );
+    };
+    (@variants $attrs:tt $vis:tt $name:tt $N:ident [$($done:tt)*] [$($var_attr:tt)*]
+        [$($label:tt)*] $V:ident $(, $($rest:tt)*)?) => {
+        $crate::__label_enum_internal!(
+            @variants $attrs $vis $name $N [$($done)* ([$($var_attr)*] $V [$($label)*])] [] []
+            $($($rest)*)?
+        );
+    };
+    (@variants [$($attr:tt)*] [$($vis:tt)*] $name:tt $N:ident
+        [$(([$($var_attr:tt)*] $V:ident [$($label:tt)*]))*] [] []) => {
+        $($attr)* $($vis)* enum $N { $($($var_attr)* $V),* }
 
             $crate::paste_crate::paste! {
+                // Not every generated item is needed by every enum.
+                #[allow(dead_code)]
                 impl $N {
+                    /// The number of variants of this enum.
+                    pub const COUNT: usize = [$(stringify!($V)),*].len();
+
+                    /// One instance of each of the enum's variants, in declaration order.
+                    pub const ALL: [Self; Self::COUNT] = [$($N::$V),*];
+
                     /// The name of this enum variant, as a string slice.
                     pub fn as_str(&self) -> &'static str {
                         match self {
-                            $($N::$V => stringify!([<$V:snake>])),*
+                            $($N::$V => $crate::__label_enum_internal!(
+                                @str [$($label)*] [<$V:snake>]
+                            )),*
+                        }
+                    }
+
+                    /// The position of this enum variant within [`Self::ALL`].
+                    pub fn index(&self) -> usize {
+                        match self {
+                            $($N::$V => $N::$V as usize),*
                         }
                     }
 
                     /// A vector containing one instance of each of the enum's variants.
                     pub fn all_variants() -> Vec<Self> {
-                        vec![$($N::$V),*]
+                        Vec::from(Self::ALL)
+                    }
+                }
+
+                impl ::std::fmt::Display for $N {
+                    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                        f.write_str(self.as_str())
+                    }
+                }
+
+                impl ::std::str::FromStr for $N {
+                    type Err = $crate::ParseLabelError;
+
+                    fn from_str(s: &str) -> ::std::result::Result<Self, $crate::ParseLabelError> {
+                        for variant in Self::ALL {
+                            if variant.as_str() == s {
+                                return Ok(variant);
+                            }
+                        }
+                        Err($crate::ParseLabelError::new(stringify!($N), s))
+                    }
+                }
+
+                impl $crate::LabelValue for $N {
+                    fn as_label_value(&self) -> &str {
+                        self.as_str()
+                    }
+
+                    fn possible_values() -> Vec<&'static str> {
+                        Self::ALL.iter().map(Self::as_str).collect()
                     }
                 }
             }
+
+            $crate::__label_enum_internal!(@labels $name $N);
+    };
+    (@labels [] $N:ident) => {};
+    (@labels [$name:literal] $N:ident) => {
+        impl $crate::Labels for $N {
+            fn label_names() -> Vec<&'static str> {
+                vec![$name]
+            }
+
+            fn possible_label_values() -> Vec<$crate::LabelValues<'static>> {
+                Self::ALL.iter().map(|v| vec![v.as_str()]).collect()
+            }
+
+            fn label_values(&self) -> $crate::LabelValues<'_> {
+                vec![self.as_str()]
+            }
+
+            fn visit_label_values<'a, R>(&'a self, f: impl FnOnce(&[&'a str]) -> R) -> R {
+                f(&[self.as_str()])
+            }
+        }
+
+        impl $crate::FiniteLabels for $N {
+            const CARDINALITY: usize = Self::COUNT;
+
+            fn label_index(&self) -> usize {
+                self.index()
+            }
+        }
+    };
+    (@str [$l:literal] $snake:ident) => {
+        $l
+    };
+    (@str [] $snake:ident) => {
+        stringify!($snake)
     };
 }
 
@@ -72,12 +257,42 @@ macro_rules! __label_enum_internal {
 ///
 /// This helper macro can only be used to define enums consisting of tags without values.
 /// Each tag corresponds to a possible Prometheus label value. The macro then generates
-/// two functions, `as_str` and `all_variants`, as described in the example below. Those
-/// funtions are intended to assist in implementing the [`Labels`] trait for a label struct
-/// that contains the enum, ensuring a consistent conversion to strings for label values,
-/// and that all possible variants are included when implementing `possible_label_values`.
+/// the functions and constants described in the example below. Those are intended to assist
+/// in implementing the [`Labels`] trait for a label struct that contains the enum, ensuring
+/// a consistent conversion to strings for label values, and that all possible variants are
+/// included when implementing `possible_label_values`.
+///
+/// By default, the label value of a variant is its name in `snake_case`. A variant can be given
+/// a different label value with a `#[label = "..."]` attribute, which is useful for values that
+/// are not valid identifiers, or to keep a label value stable when renaming a variant.
+///
+/// The enum also implements [`LabelValue`], so it can be used as a field of a struct with
+/// `#[derive(Labels)]`, as well as [`Display`] and [`FromStr`], both of which use the label
+/// value of each variant.
+///
+/// When the enum alone describes the labels of a metric, give the enum itself a
+/// `#[label = "..."]` attribute naming the label. The macro then implements [`Labels`] (and
+/// [`FiniteLabels`]) for the enum, with that one label name and every variant as a possible
+/// value:
+///
+/// ```ignore
+/// label_enum! {
+///     #[label = "method"]
+///     pub enum Method {
+///         Get,
+///         Post,
+///     }
+/// }
+///
+/// let requests = IntCounterWithLabels::<Method>::register_new("requests", "HTTP requests");
+/// requests.inc(&Method::Get);
+/// ```
 ///
+/// [`Display`]: std::fmt::Display
+/// [`FiniteLabels`]: trait.FiniteLabels.html
+/// [`FromStr`]: std::str::FromStr
 /// [`Labels`]: trait.Labels.html
+/// [`LabelValue`]: trait.LabelValue.html
 ///
 /// # Example
 ///
@@ -88,32 +303,137 @@ macro_rules! __label_enum_internal {
 ///     pub(crate) enum MyErrorLabel {
 ///         IoError,
 ///         TimeoutError,
+///         #[label = "oom"]
 ///         MemoryError,
 ///     }
 /// }
 /// ```
 ///
-/// The macro will declare the enum exactly as provided. But in addition, it will generate
-/// two functions:
+/// The macro will declare the enum exactly as provided, apart from removing any `#[label]`
+/// attributes. But in addition, it will generate the following:
 ///
 /// ```ignore
 /// impl MyErrorLabel {
+///     /// The number of variants of this enum.
+///     pub const COUNT: usize = 3;
+///
+///     /// One instance of each of the enum's variants, in declaration order.
+///     pub const ALL: [Self; Self::COUNT] = [ ... ];
+///
 ///     /// The name of this enum variant, as a string slice.
 ///     pub fn as_str(&self) -> &'static str { ... }
 ///
+///     /// The position of this enum variant within `Self::ALL`.
+///     pub fn index(&self) -> usize { ... }
+///
 ///     /// A vector containing one instance of each of the enum's variants.
 ///     pub fn all_variants() -> Vec<Self> { ... }
 /// }
 /// ```
+///
+/// Here, `MyErrorLabel::MemoryError.as_str()` is `"oom"`, and `"io_error".parse()` returns
+/// `Ok(MyErrorLabel::IoError)`.
 #[macro_export(local_inner_macros)]
 macro_rules! label_enum {
-    ($(#[$attr:meta])* enum $N:ident { $($(#[$var_attr:meta])* $V:ident),* $(,)* }) => {
-        __label_enum_internal!($(#[$attr])* () enum $N { $($(#[$var_attr])* $V),* });
+    ($(#[$($attr:tt)*])* enum $N:ident $body:tt) => {
+        __label_enum_internal!(@attrs [] [] () $N $body $(#[$($attr)*])*);
     };
-    ($(#[$attr:meta])* pub enum $N:ident { $($(#[$var_attr:meta])* $V:ident),* $(,)* }) => {
-        __label_enum_internal!($(#[$attr])* (pub) enum $N { $($(#[$var_attr])* $V),* });
+    ($(#[$($attr:tt)*])* pub enum $N:ident $body:tt) => {
+        __label_enum_internal!(@attrs [] [] (pub) $N $body $(#[$($attr)*])*);
     };
-    ($(#[$attr:meta])* pub ($($vis:tt)+) enum $N:ident { $($(#[$var_attr:meta])* $V:ident),* $(,)* }) => {
-        __label_enum_internal!($(#[$attr])* (pub ($($vis)+)) enum $N { $($(#[$var_attr])* $V),* });
+    ($(#[$($attr:tt)*])* pub ($($vis:tt)+) enum $N:ident $body:tt) => {
+        __label_enum_internal!(@attrs [] [] (pub ($($vis)+)) $N $body $(#[$($attr)*])*);
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{FiniteLabels, Labels, ParseLabelError};
+
+    label_enum! {
+        /// Classes of HTTP status codes.
+        #[derive(Debug, PartialEq)]
+        pub(crate) enum StatusClass {
+            /// Successful responses.
+            #[label = "2xx"]
+            Success,
+            #[label = "5xx"]
+            /// Server errors.
+            ServerError,
+            NotModified
+        }
+    }
+
+    label_enum! {
+        #[derive(Debug)]
+        #[label = "method"]
+        enum Method {
+            Get,
+            Post,
+        }
+    }
+
+    #[test]
+    fn label_enum_implements_labels_when_named() {
+        assert_eq!(Method::label_names(), vec!["method"]);
+        assert_eq!(
+            Method::possible_label_values(),
+            vec![vec!["get"], vec!["post"]]
+        );
+        assert_eq!(Method::Post.label_values(), vec!["post"]);
+        assert_eq!(Method::CARDINALITY, 2);
+        assert_eq!(Method::Post.label_index(), 1);
+    }
+
+    #[test]
+    fn label_enum_uses_overridden_label_values() {
+        assert_eq!(StatusClass::Success.as_str(), "2xx");
+        assert_eq!(StatusClass::ServerError.as_str(), "5xx");
+        assert_eq!(StatusClass::NotModified.as_str(), "not_modified");
+        assert_eq!(StatusClass::ServerError.to_string(), "5xx");
+    }
+
+    #[test]
+    fn label_enum_parses_label_values() {
+        assert_eq!("5xx".parse(), Ok(StatusClass::ServerError));
+        assert_eq!("not_modified".parse(), Ok(StatusClass::NotModified));
+        assert_eq!(
+            "ServerError".parse::<StatusClass>(),
+            Err(ParseLabelError::new("StatusClass", "ServerError"))
+        );
+    }
+
+    #[test]
+    fn label_enum_enumerates_variants() {
+        assert_eq!(StatusClass::COUNT, 3);
+        assert_eq!(
+            StatusClass::ALL,
+            [
+                StatusClass::Success,
+                StatusClass::ServerError,
+                StatusClass::NotModified
+            ]
+        );
+        for (i, variant) in StatusClass::ALL.iter().enumerate() {
+            assert_eq!(variant.index(), i);
+        }
+        assert_eq!(StatusClass::all_variants().len(), StatusClass::COUNT);
+    }
+
+    label_enum! {
+        #[derive(Debug, PartialEq)]
+        enum ResultLabel {
+            Ok,
+            Err,
+        }
+    }
+
+    #[test]
+    fn label_enum_allows_variants_named_like_result() {
+        assert_eq!("ok".parse(), Ok(ResultLabel::Ok));
+        assert_eq!("err".parse(), Ok(ResultLabel::Err));
+        assert!("error".parse::<ResultLabel>().is_err());
+        assert_eq!(ResultLabel::Err.index(), 1);
+        assert_eq!(ResultLabel::all_variants().len(), ResultLabel::COUNT);
+    }
+}
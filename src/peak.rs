@@ -0,0 +1,287 @@
+//! Gauges that also report the highest value they reached between scrapes.
+
+use crate::{
+    error::Result,
+    guards::{GenericGaugeGuard, GenericGaugeSetGuard, GuardedGauge},
+    Windowing,
+};
+use prometheus::{
+    core::{AtomicI64, Collector, Desc, GenericGauge},
+    proto::MetricFamily,
+    IntGauge, Registry,
+};
+use std::sync::{atomic, atomic::Ordering, Arc};
+
+/// The highest value observed during one window of a [`PeakGauge`].
+struct PeakWindow(atomic::AtomicI64);
+
+impl Default for PeakWindow {
+    fn default() -> Self {
+        // `i64::MIN` marks a window in which nothing has been observed yet.
+        Self(atomic::AtomicI64::new(i64::MIN))
+    }
+}
+
+/// An integer gauge that is exported along with a companion gauge reporting the highest value it
+/// reached since the last scrape.
+///
+/// A gauge tracking in-flight work, for example with [`GuardedGauge::guarded_inc`], only shows
+/// its value at the time of a scrape. Short spikes between scrapes are visible in the `_peak`
+/// gauge instead.
+///
+/// Peaks are tracked in the windows of a [`Windowing`], which is rotated every time the gauge is
+/// collected by a registry, or when [`PeakGauge::rotate`] is called. Each window starts at the
+/// value of the gauge when it became current. Like [`Windowing`], tracking is best effort: an
+/// increment that races with a rotation may be missed by the `_peak` gauge, although it is always
+/// reflected in the gauge itself.
+///
+/// `PeakGauge` implements [`GuardedGauge`], so it can be used with
+/// [`InstrumentedFuture::with_count_gauge`][crate::InstrumentedFuture::with_count_gauge] and
+/// [`InstrumentedFuture::with_count_gauge_owned`][crate::InstrumentedFuture::with_count_gauge_owned].
+///
+/// # Examples
+///
+/// ```
+/// use prometheus_utils::{GuardedGauge, PeakGauge};
+///
+/// let in_flight = PeakGauge::new("requests_in_flight", "requests being handled").unwrap();
+/// {
+///     let _first = in_flight.guarded_inc();
+///     let _second = in_flight.guarded_inc();
+/// }
+/// assert_eq!(in_flight.get(), 0);
+/// assert_eq!(in_flight.rotate(), 2);
+/// assert_eq!(in_flight.rotate(), 0);
+/// ```
+#[derive(Clone)]
+pub struct PeakGauge {
+    gauge: Arc<IntGauge>,
+    peak: IntGauge,
+    windows: Arc<Windowing<PeakWindow>>,
+}
+
+impl PeakGauge {
+    /// Construct a new gauge named `name`, without registering it.
+    ///
+    /// The companion gauge is named `name` followed by `_peak`.
+    pub fn new(name: &str, help: &str) -> Result<Self> {
+        let gauge = IntGauge::new(name, help)?;
+        let peak = IntGauge::new(
+            format!("{}_peak", name),
+            format!("The highest value of {} since the last scrape", name),
+        )?;
+        Ok(Self {
+            gauge: Arc::new(gauge),
+            peak,
+            windows: Arc::new(Windowing::new()),
+        })
+    }
+
+    /// Construct a new gauge and register it in `registry`.
+    pub fn try_register_in(registry: &Registry, name: &str, help: &str) -> Result<Self> {
+        let gauge = Self::new(name, help)?;
+        gauge.register_in(registry)?;
+        Ok(gauge)
+    }
+
+    /// Construct and immediately register a new gauge.
+    ///
+    /// The metric is registered in the default registry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric cannot be constructed or registered. See
+    /// [`PeakGauge::try_register_in`] for a fallible alternative.
+    pub fn register_new(name: &str, help: &str) -> Self {
+        Self::try_register_in(prometheus::default_registry(), name, help).unwrap()
+    }
+
+    /// Register this gauge, along with its companion `_peak` gauge, in `registry`.
+    pub fn register_in(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.clone()))?;
+        Ok(())
+    }
+
+    /// Increase the gauge by `1`.
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    /// Decrease the gauge by `1`.
+    pub fn dec(&self) {
+        self.gauge.dec();
+    }
+
+    /// Increase the gauge by `v`.
+    pub fn add(&self, v: i64) {
+        self.gauge.add(v);
+        self.record();
+    }
+
+    /// Decrease the gauge by `v`.
+    pub fn sub(&self, v: i64) {
+        self.gauge.sub(v);
+    }
+
+    /// Set the gauge to `v`.
+    pub fn set(&self, v: i64) {
+        self.gauge.set(v);
+        self.record();
+    }
+
+    /// The current value of the gauge.
+    pub fn get(&self) -> i64 {
+        self.gauge.get()
+    }
+
+    /// Start a new window, returning the highest value the gauge reached during the previous one.
+    ///
+    /// The returned value is also exported as the `_peak` gauge.
+    pub fn rotate(&self) -> i64 {
+        let previous = self.windows.cycle_windows();
+        // An update that raced with an earlier rotation may have landed in the new window after
+        // it was last read, so it is reset rather than reporting that update much later.
+        self.windows.current().0.store(i64::MIN, Ordering::SeqCst);
+        let peak = previous.0.swap(i64::MIN, Ordering::SeqCst);
+        let current = self.gauge.get();
+        self.windows
+            .current()
+            .0
+            .fetch_max(current, Ordering::SeqCst);
+
+        let peak = peak.max(current);
+        self.peak.set(peak);
+        peak
+    }
+
+    /// Record the current value of the gauge in the current window.
+    fn record(&self) {
+        let value = self.gauge.get();
+        self.windows.current().0.fetch_max(value, Ordering::SeqCst);
+    }
+}
+
+impl GuardedGauge<AtomicI64> for PeakGauge {
+    fn guarded_inc(&self) -> GenericGaugeGuard<AtomicI64, &GenericGauge<AtomicI64>> {
+        self.guarded_add(1)
+    }
+
+    fn guarded_add(&self, v: i64) -> GenericGaugeGuard<AtomicI64, &GenericGauge<AtomicI64>> {
+        let guard = self.gauge.guarded_add(v);
+        self.record();
+        guard
+    }
+
+    fn guarded_inc_owned(
+        self: &Arc<Self>,
+    ) -> GenericGaugeGuard<AtomicI64, Arc<GenericGauge<AtomicI64>>> {
+        self.guarded_add_owned(1)
+    }
+
+    fn guarded_add_owned(
+        self: &Arc<Self>,
+        v: i64,
+    ) -> GenericGaugeGuard<AtomicI64, Arc<GenericGauge<AtomicI64>>> {
+        let guard = self.gauge.guarded_add_owned(v);
+        self.record();
+        guard
+    }
+
+    fn guarded_set(&self, v: i64) -> GenericGaugeSetGuard<AtomicI64, &GenericGauge<AtomicI64>> {
+        let guard = self.gauge.guarded_set(v);
+        self.record();
+        guard
+    }
+
+    fn guarded_set_owned(
+        self: &Arc<Self>,
+        v: i64,
+    ) -> GenericGaugeSetGuard<AtomicI64, Arc<GenericGauge<AtomicI64>>> {
+        let guard = self.gauge.guarded_set_owned(v);
+        self.record();
+        guard
+    }
+}
+
+/// Collecting a `PeakGauge` rotates its windows, so the `_peak` gauge covers the time since the
+/// previous scrape.
+impl Collector for PeakGauge {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut desc = self.gauge.desc();
+        desc.extend(self.peak.desc());
+        desc
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.rotate();
+        let mut families = self.gauge.collect();
+        families.extend(self.peak.collect());
+        families
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PeakGauge;
+    use crate::{percentile::SAMPLING_WINDOWS, GuardedGauge, IntoInstrumentedFuture};
+    use prometheus::Registry;
+    use std::sync::{atomic::Ordering, Arc};
+
+    #[test]
+    fn late_updates_to_previous_windows_are_not_reported() {
+        let gauge = PeakGauge::new("late_peaks", "help").unwrap();
+
+        // Simulate an update that read the current window just before a rotation, but only
+        // recorded its value afterwards.
+        let window = gauge.windows.current();
+        assert_eq!(gauge.rotate(), 0);
+        window.0.fetch_max(100, Ordering::SeqCst);
+
+        for _ in 0..2 * SAMPLING_WINDOWS {
+            assert_eq!(gauge.rotate(), 0);
+        }
+    }
+
+    #[test]
+    fn peaks_are_reported_per_scrape() {
+        let registry = Registry::new();
+        let gauge = PeakGauge::try_register_in(&registry, "in_flight", "help").unwrap();
+        let scrape = || {
+            registry
+                .gather()
+                .iter()
+                .map(|family| family.get_metric()[0].get_gauge().get_value())
+                .collect::<Vec<_>>()
+        };
+
+        let guard = gauge.guarded_inc();
+        drop(gauge.guarded_add(2));
+        drop(gauge.guarded_set(5));
+        assert_eq!(scrape(), [1.0, 5.0]);
+
+        // The next window starts at the value the gauge had when the window began.
+        assert_eq!(scrape(), [1.0, 1.0]);
+        drop(guard);
+        assert_eq!(scrape(), [0.0, 1.0]);
+        assert_eq!(scrape(), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn instrumented_futures_update_peaks() {
+        let gauge = Arc::new(PeakGauge::new("peak_futures", "help").unwrap());
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("can build runtime");
+
+        let first = tokio::task::yield_now()
+            .into_instrumented_future()
+            .with_count_gauge_owned(Arc::clone(&gauge));
+        let second = tokio::task::yield_now()
+            .into_instrumented_future()
+            .with_count_gauge_owned(Arc::clone(&gauge));
+        rt.block_on(async { tokio::join!(first, second) });
+
+        assert_eq!(gauge.get(), 0);
+        assert_eq!(gauge.rotate(), 2);
+    }
+}
@@ -1,4 +1,3 @@
-use crate::{LabelValues, Labels};
 use num_traits::Zero;
 use parking_lot::Mutex;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -11,7 +10,7 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 /// samples can continue to be recorded. Four windows is only additionally
 /// meaningful if we ever want to record actual samples of prior sampling
 /// windows.
-const SAMPLING_WINDOWS: usize = 4;
+pub(crate) const SAMPLING_WINDOWS: usize = 4;
 
 /// [`Windowing`] is a mechanism for rotating between different observations.
 /// It provides an accessor [`Windowing::current`] for the current
@@ -23,6 +22,12 @@ pub struct Windowing<P> {
     windows: [Box<P>; SAMPLING_WINDOWS],
 }
 
+impl<P: Default> Default for Windowing<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<P: Default> Windowing<P> {
     /// Constructor. Initializes its owned ring of `P`s using [`Default::default()`].
     pub fn new() -> Self {
@@ -184,21 +189,21 @@ impl<T: Ord + Zero + Copy> Observations<T> {
         let sorted = observations.sorted_data();
 
         fn percentile<T: Ord + Zero + Copy>(sorted_ts: &[T], p: f64) -> T {
-            if sorted_ts.len() == 0 {
+            if sorted_ts.is_empty() {
                 T::zero()
             } else {
                 let percentile_idx = ((sorted_ts.len() as f64 * p) / 100.0) as usize;
                 sorted_ts[percentile_idx]
             }
         }
-        let p25 = percentile(&sorted, 25.0);
-        let p50 = percentile(&sorted, 50.0);
-        let p75 = percentile(&sorted, 75.0);
-        let p90 = percentile(&sorted, 90.0);
-        let p95 = percentile(&sorted, 95.0);
-        let p99 = percentile(&sorted, 99.0);
-        let p99p9 = percentile(&sorted, 99.9);
-        let max = sorted.last().map(|x| *x).unwrap_or_else(|| T::zero());
+        let p25 = percentile(sorted, 25.0);
+        let p50 = percentile(sorted, 50.0);
+        let p75 = percentile(sorted, 75.0);
+        let p90 = percentile(sorted, 90.0);
+        let p95 = percentile(sorted, 95.0);
+        let p99 = percentile(sorted, 99.0);
+        let p99p9 = percentile(sorted, 99.9);
+        let max = sorted.last().copied().unwrap_or_else(T::zero);
         let count = sorted.len();
         observations.clear();
         std::mem::drop(observations);
@@ -236,6 +241,7 @@ impl<T: Ord + Zero + Copy> Observations<T> {
 
 crate::label_enum! {
     /// Labels corresponding to the fields in [`Sample`]
+    #[label = "bucket"]
     pub enum TimingBucket {
         /// 25th percentile observation
         P25,
@@ -258,21 +264,6 @@ crate::label_enum! {
     }
 }
 
-impl Labels for TimingBucket {
-    fn label_names() -> Vec<&'static str> {
-        vec!["bucket"]
-    }
-    fn possible_label_values() -> Vec<LabelValues<'static>> {
-        Self::all_variants()
-            .into_iter()
-            .map(|b| vec![b.as_str()])
-            .collect()
-    }
-    fn label_values(&self) -> LabelValues {
-        vec![self.as_str()]
-    }
-}
-
 impl<T: Ord + Zero + Copy + Into<i64>> Sample<T> {
     /// Returns each member of the struct along with its [`TimingBucket`]
     /// label.  Each percentile is given as an i64.
@@ -0,0 +1,488 @@
+//! Gauges that track how many things are in each state of a state machine.
+
+use crate::{
+    error::Result, CounterWithLabels, FiniteLabels, HandleTable, IntGaugeWithLabels, SeriesHandle,
+};
+use parking_lot::{Mutex, RwLock};
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    Counter, IntGauge, Registry,
+};
+use std::{sync::Arc, time::Instant};
+
+/// A collector for a gauge whose series are updated together, which waits for updates in
+/// progress so that a scrape never sees one half-applied.
+///
+/// Updates hold a read lock on `updates`, so they only wait for each other when a scrape is
+/// pending.
+pub(crate) struct ConsistentCollector {
+    pub(crate) collector: Box<dyn Collector>,
+    pub(crate) updates: Arc<RwLock<()>>,
+}
+
+impl Collector for ConsistentCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.collector.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let _updates = self.updates.write();
+        self.collector.collect()
+    }
+}
+
+/// The counter accumulating time spent in each state, and its handles.
+type TimeInState<E> = (CounterWithLabels<E>, HandleTable<E, SeriesHandle<Counter>>);
+
+/// A gauge counting how many things, such as connections or jobs, are in each state of a state
+/// machine.
+///
+/// The states are described by a [`FiniteLabels`] type, typically an enum declared with
+/// [`label_enum!`] and a `#[label = "..."]` name. Each thing being tracked holds a
+/// [`StateGuard`], which counts it in exactly one state until the guard is dropped.
+///
+/// Transitions are atomic with respect to scrapes: a registry gathering the gauge sees a guard
+/// counted in either its old or its new state, never in both or neither.
+///
+/// A `StateGauge` can also accumulate the number of seconds spent in each state, see
+/// [`StateGauge::with_time_in_state`].
+///
+/// [`label_enum!`]: macro.label_enum.html
+///
+/// # Examples
+///
+/// ```
+/// use prometheus_utils::{label_enum, StateGauge};
+///
+/// label_enum! {
+///     #[label = "state"]
+///     enum ConnectionState {
+///         Handshake,
+///         Active,
+///         Closing,
+///     }
+/// }
+///
+/// let connections = StateGauge::<ConnectionState>::new("connections", "open connections")
+///     .unwrap()
+///     .with_time_in_state("connection_state_seconds", "time connections spent in each state")
+///     .unwrap();
+///
+/// let mut conn = connections.enter(ConnectionState::Handshake);
+/// conn.transition(ConnectionState::Active);
+/// assert_eq!(connections.count(&ConnectionState::Active), 1);
+///
+/// drop(conn);
+/// assert_eq!(connections.count(&ConnectionState::Active), 0);
+/// ```
+pub struct StateGauge<E: FiniteLabels> {
+    gauge: IntGaugeWithLabels<E>,
+    counts: HandleTable<E, SeriesHandle<IntGauge>>,
+    time_in_state: Option<TimeInState<E>>,
+    transitions: Arc<RwLock<()>>,
+}
+
+impl<E: FiniteLabels> StateGauge<E> {
+    /// Create a new state gauge, with every state starting at zero.
+    ///
+    /// The gauge is not registered; see [`StateGauge::register_in`].
+    pub fn new(name: &str, help: &str) -> Result<Self> {
+        let gauge = IntGaugeWithLabels::new(name, help)?;
+        let counts = gauge.handles();
+        Ok(Self {
+            gauge,
+            counts,
+            time_in_state: None,
+            transitions: Arc::default(),
+        })
+    }
+
+    /// Construct a new state gauge and register it in `registry`.
+    pub fn try_register_in(registry: &Registry, name: &str, help: &str) -> Result<Self> {
+        let gauge = Self::new(name, help)?;
+        gauge.register_in(registry)?;
+        Ok(gauge)
+    }
+
+    /// Construct and immediately register a new state gauge.
+    ///
+    /// The metric is registered in the default registry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric cannot be constructed or registered. See
+    /// [`StateGauge::try_register_in`] for a fallible alternative.
+    pub fn register_new(name: &str, help: &str) -> Self {
+        Self::try_register_in(prometheus::default_registry(), name, help).unwrap()
+    }
+
+    /// Also accumulate the seconds spent in each state, in a counter with the provided `name`.
+    ///
+    /// Time is recorded when a [`StateGuard`] leaves a state, either by transitioning or by being
+    /// dropped, so time spent in the current state is not reflected until then.
+    ///
+    /// The counter is registered along with the gauge by [`StateGauge::register_in`], so this
+    /// should be called before the gauge is registered.
+    pub fn with_time_in_state(mut self, name: &str, help: &str) -> Result<Self> {
+        let counter = CounterWithLabels::new(name, help)?;
+        let handles = counter.handles();
+        self.time_in_state = Some((counter, handles));
+        Ok(self)
+    }
+
+    /// Register the gauge, and the time-in-state counter if there is one, in `registry`.
+    pub fn register_in(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(ConsistentCollector {
+            collector: self.gauge.collector(),
+            updates: self.transitions.clone(),
+        }))?;
+        if let Some((counter, _)) = &self.time_in_state {
+            if let Err(err) = counter.register_in(registry) {
+                // Leave the registry as it was, rather than with only the gauge registered.
+                let _ = registry.unregister(self.gauge.collector());
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Count one thing in `state`, until the returned guard is dropped.
+    ///
+    /// The guard can be moved to other states with [`StateGuard::transition`].
+    #[must_use]
+    pub fn enter(&self, state: E) -> StateGuard<'_, E> {
+        self.counts.get(&state).inc();
+        StateGuard {
+            gauge: self,
+            state,
+            entered: Instant::now(),
+        }
+    }
+
+    /// The number of things currently in `state`.
+    pub fn count(&self, state: &E) -> i64 {
+        self.counts.get(state).get()
+    }
+
+    /// The number of seconds spent in `state` by guards that have since left it, if the gauge
+    /// was created with [`StateGauge::with_time_in_state`].
+    pub fn time_in_state(&self, state: &E) -> Option<f64> {
+        self.time_in_state
+            .as_ref()
+            .map(|(_, handles)| handles.get(state).get())
+    }
+
+    /// Record the time spent in `state` since `entered`.
+    fn record_time(&self, state: &E, entered: Instant) {
+        if let Some((_, handles)) = &self.time_in_state {
+            handles.get(state).inc_by(entered.elapsed().as_secs_f64());
+        }
+    }
+}
+
+/// An RAII-style guard that counts one thing in the current state of a [`StateGauge`].
+///
+/// Created by calling [`StateGauge::enter`].
+pub struct StateGuard<'a, E: FiniteLabels> {
+    gauge: &'a StateGauge<E>,
+    state: E,
+    entered: Instant,
+}
+
+impl<'a, E: FiniteLabels> StateGuard<'a, E> {
+    /// Move this thing from its current state to `new_state`.
+    ///
+    /// The move is atomic with respect to scrapes of the gauge, which see this thing in exactly
+    /// one of the two states.
+    pub fn transition(&mut self, new_state: E) {
+        {
+            let _transition = self.gauge.transitions.read();
+            self.gauge.counts.get(&new_state).inc();
+            self.gauge.counts.get(&self.state).dec();
+        }
+
+        let now = Instant::now();
+        self.gauge.record_time(&self.state, self.entered);
+        self.state = new_state;
+        self.entered = now;
+    }
+
+    /// The current state.
+    pub fn state(&self) -> &E {
+        &self.state
+    }
+}
+
+/// When a state guard is dropped, it is no longer counted in its current state.
+impl<'a, E: FiniteLabels> Drop for StateGuard<'a, E> {
+    fn drop(&mut self) {
+        self.gauge.counts.get(&self.state).dec();
+        self.gauge.record_time(&self.state, self.entered);
+    }
+}
+
+/// A metric reporting which one of a set of states something is currently in, like an
+/// OpenMetrics `StateSet`.
+///
+/// The states are described by a [`FiniteLabels`] type, typically an enum declared with
+/// [`label_enum!`] and a `#[label = "..."]` name. Every state is exported: the current state with
+/// the value `1`, and every other state with the value `0`. Changing the state is atomic with
+/// respect to scrapes, which always see exactly one current state.
+///
+/// [`label_enum!`]: macro.label_enum.html
+///
+/// # Examples
+///
+/// ```
+/// use prometheus_utils::{label_enum, StateSet};
+///
+/// label_enum! {
+///     #[label = "breaker_state"]
+///     enum BreakerState {
+///         Closed,
+///         HalfOpen,
+///         Open,
+///     }
+/// }
+///
+/// let breaker =
+///     StateSet::new("breaker_state", "circuit breaker state", BreakerState::Closed).unwrap();
+/// breaker.set(&BreakerState::Open);
+/// assert!(breaker.is_current(&BreakerState::Open));
+/// assert!(!breaker.is_current(&BreakerState::Closed));
+/// ```
+pub struct StateSet<E: FiniteLabels> {
+    gauge: IntGaugeWithLabels<E>,
+    states: HandleTable<E, SeriesHandle<IntGauge>>,
+    current: Mutex<usize>,
+    updates: Arc<RwLock<()>>,
+}
+
+impl<E: FiniteLabels> StateSet<E> {
+    /// Construct a new state set in the `initial` state, without registering it.
+    pub fn new(name: &str, help: &str, initial: E) -> Result<Self> {
+        let gauge = IntGaugeWithLabels::new(name, help)?;
+        let states = gauge.handles();
+        let current = initial.label_index();
+        states.get(&initial).set(1);
+        Ok(Self {
+            gauge,
+            states,
+            current: Mutex::new(current),
+            updates: Arc::default(),
+        })
+    }
+
+    /// Construct a new state set in the `initial` state and register it in `registry`.
+    pub fn try_register_in(
+        registry: &Registry,
+        name: &str,
+        help: &str,
+        initial: E,
+    ) -> Result<Self> {
+        let state_set = Self::new(name, help, initial)?;
+        state_set.register_in(registry)?;
+        Ok(state_set)
+    }
+
+    /// Construct and immediately register a new state set in the `initial` state.
+    ///
+    /// The metric is registered in the default registry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric cannot be constructed or registered. See
+    /// [`StateSet::try_register_in`] for a fallible alternative.
+    pub fn register_new(name: &str, help: &str, initial: E) -> Self {
+        Self::try_register_in(prometheus::default_registry(), name, help, initial).unwrap()
+    }
+
+    /// Register this state set in `registry`.
+    pub fn register_in(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(ConsistentCollector {
+            collector: self.gauge.collector(),
+            updates: self.updates.clone(),
+        }))?;
+        Ok(())
+    }
+
+    /// Make `state` the current state.
+    pub fn set(&self, state: &E) {
+        let mut current = self.current.lock();
+        let _update = self.updates.read();
+        self.states.at(*current).set(0);
+        self.states.get(state).set(1);
+        *current = state.label_index();
+    }
+
+    /// Whether `state` is the current state.
+    pub fn is_current(&self, state: &E) -> bool {
+        *self.current.lock() == state.label_index()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StateGauge, StateSet};
+    use crate::CounterWithLabels;
+    use prometheus::Registry;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    crate::label_enum! {
+        #[label = "state"]
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        enum JobState {
+            Queued,
+            Running,
+            Done,
+        }
+    }
+
+    #[test]
+    fn guards_move_between_states() {
+        let jobs = StateGauge::<JobState>::new("jobs", "help").unwrap();
+        let counts = || {
+            (
+                jobs.count(&JobState::Queued),
+                jobs.count(&JobState::Running),
+                jobs.count(&JobState::Done),
+            )
+        };
+
+        let mut first = jobs.enter(JobState::Queued);
+        let second = jobs.enter(JobState::Queued);
+        assert_eq!(counts(), (2, 0, 0));
+
+        first.transition(JobState::Running);
+        assert_eq!(*first.state(), JobState::Running);
+        assert_eq!(counts(), (1, 1, 0));
+
+        drop(first);
+        drop(second);
+        assert_eq!(counts(), (0, 0, 0));
+        assert_eq!(jobs.time_in_state(&JobState::Queued), None);
+    }
+
+    #[test]
+    fn time_in_state_is_recorded_when_leaving_a_state() {
+        let jobs = StateGauge::<JobState>::new("jobs", "help")
+            .unwrap()
+            .with_time_in_state("job_state_seconds", "help")
+            .unwrap();
+        let registry = Registry::new();
+        jobs.register_in(&registry).unwrap();
+
+        let mut job = jobs.enter(JobState::Queued);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(jobs.time_in_state(&JobState::Queued), Some(0.0));
+
+        job.transition(JobState::Running);
+        assert!(jobs.time_in_state(&JobState::Queued).unwrap() >= 0.01);
+        assert_eq!(jobs.time_in_state(&JobState::Running), Some(0.0));
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        drop(job);
+        assert!(jobs.time_in_state(&JobState::Running).unwrap() >= 0.01);
+
+        let names: Vec<_> = registry
+            .gather()
+            .iter()
+            .map(|family| family.get_name().to_owned())
+            .collect();
+        assert_eq!(names, ["job_state_seconds", "jobs"]);
+    }
+
+    #[test]
+    fn failed_registrations_leave_the_registry_unchanged() {
+        let registry = Registry::new();
+        CounterWithLabels::<JobState>::try_register_in(&registry, "taken_seconds", "help").unwrap();
+
+        let jobs = StateGauge::<JobState>::new("taken", "help")
+            .unwrap()
+            .with_time_in_state("taken_seconds", "help")
+            .unwrap();
+        assert!(jobs.register_in(&registry).is_err());
+        // The gauge was unregistered again, so it can be registered on its own.
+        StateGauge::<JobState>::try_register_in(&registry, "taken", "help").unwrap();
+    }
+
+    #[test]
+    fn scrapes_never_see_half_applied_transitions() {
+        let registry = Registry::new();
+        let jobs = StateGauge::<JobState>::try_register_in(&registry, "busy_jobs", "help").unwrap();
+        let total = || -> f64 {
+            registry.gather()[0]
+                .get_metric()
+                .iter()
+                .map(|m| m.get_gauge().get_value())
+                .sum()
+        };
+
+        let mut job = jobs.enter(JobState::Queued);
+        let scraping = AtomicBool::new(true);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                while scraping.load(Ordering::Relaxed) {
+                    job.transition(JobState::Running);
+                    job.transition(JobState::Queued);
+                }
+            });
+            for _ in 0..1000 {
+                assert_eq!(total(), 1.0);
+            }
+            scraping.store(false, Ordering::Relaxed);
+        });
+    }
+
+    #[test]
+    fn state_sets_export_exactly_one_current_state() {
+        let registry = Registry::new();
+        let state = StateSet::try_register_in(&registry, "job", "help", JobState::Queued).unwrap();
+        let exported = || {
+            let families = registry.gather();
+            families[0]
+                .get_metric()
+                .iter()
+                .map(|m| m.get_gauge().get_value())
+                .collect::<Vec<_>>()
+        };
+        // Series are exported sorted by label value: "done", "queued", "running".
+        assert_eq!(exported(), [0.0, 1.0, 0.0]);
+
+        state.set(&JobState::Running);
+        assert_eq!(exported(), [0.0, 0.0, 1.0]);
+        state.set(&JobState::Running);
+        assert_eq!(exported(), [0.0, 0.0, 1.0]);
+        assert!(state.is_current(&JobState::Running));
+        assert!(!state.is_current(&JobState::Queued));
+    }
+
+    #[test]
+    fn scrapes_never_see_half_applied_state_changes() {
+        let registry = Registry::new();
+        let state =
+            StateSet::try_register_in(&registry, "job_phase", "help", JobState::Queued).unwrap();
+        let current_states = || -> f64 {
+            registry.gather()[0]
+                .get_metric()
+                .iter()
+                .map(|m| m.get_gauge().get_value())
+                .sum()
+        };
+
+        let scraping = AtomicBool::new(true);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                while scraping.load(Ordering::Relaxed) {
+                    state.set(&JobState::Running);
+                    state.set(&JobState::Done);
+                }
+            });
+            for _ in 0..1000 {
+                assert_eq!(current_states(), 1.0);
+            }
+            scraping.store(false, Ordering::Relaxed);
+        });
+    }
+}